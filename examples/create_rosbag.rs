@@ -1,5 +1,5 @@
 use anyhow::Result;
-use rosbag2_rs::Writer;
+use rosbag2_rs::{QosProfile, Writer};
 use std::path::Path;
 
 fn main() -> Result<()> {
@@ -14,26 +14,10 @@ fn main() -> Result<()> {
     let topic = "example_topic";
     let msgtype = "std_msgs/msg/Int32";
 
-    // Add a connection for this message type
-    // adjust qos profile according to actual ROS2 message types (https://docs.ros2.org/latest/api/rmw/structrmw__qos__profile__t.html)
-    const LATCH: &str = r#"- history: 3
-  depth: 0
-  reliability: 1
-  durability: 1
-  deadline:
-    sec: 2147483647
-    nsec: 4294967295
-  lifespan:
-    sec: 2147483647
-    nsec: 4294967295
-  liveliness: 1
-  liveliness_lease_duration:
-    sec: 2147483647
-    nsec: 4294967295
-  avoid_ros_namespace_conventions: false
-"#;
-
-    let connection = writer.add_connection(topic, msgtype, "cdr", LATCH)?;
+    // Add a connection for this message type, offered like a latched publisher
+    // (adjust the QoS profile according to the actual publisher)
+    let connection =
+        writer.add_connection_with_qos(topic, msgtype, "cdr", &[QosProfile::latched()])?;
 
     // Write some dummy messages
     for i in 0..50 {
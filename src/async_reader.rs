@@ -0,0 +1,110 @@
+//! Reading bags from async code, enabled by the `tokio` feature.
+//!
+//! SQLite has no async interface, so [`AsyncReader`] runs a [`Reader`] on the blocking
+//! thread pool of tokio and sends the messages through a bounded channel. The reading
+//! thread waits while the channel is full, so a slow consumer does not make it buffer
+//! the whole bag, and it stops once the stream is dropped. Every stream reads through a
+//! [`Reader::try_clone`] of its own, so streams of the same reader run side by side.
+
+use crate::*;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+
+/// Number of messages buffered between the reading thread and the stream by default.
+const DEFAULT_CAPACITY: usize = 64;
+
+/// Async counterpart of [`Reader`], streaming messages read on a blocking thread.
+///
+/// ```no_run
+/// use rosbag2_rs::{AsyncReader, ReadFilter};
+/// use tokio_stream::StreamExt;
+///
+/// # async fn read() -> rosbag2_rs::Result<()> {
+/// let reader = AsyncReader::new("path/to/bag").await?;
+/// let mut messages = reader.messages(ReadFilter::default()).await;
+/// while let Some(message) = messages.next().await {
+///     let message = message?;
+///     println!("{} at {:?}", message.connection.topic, message.timestamp);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncReader {
+    pub metadata: Metadata,
+    pub connections: Vec<TopicConnection>,
+    /// SQLite connections cannot be shared between threads, the lock is only held while
+    /// a stream clones the reader.
+    reader: Arc<Mutex<Reader>>,
+    capacity: usize,
+}
+
+impl AsyncReader {
+    /// Opens the bag at `path` like [`Reader::new`], on a blocking thread.
+    pub async fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let reader = tokio::task::spawn_blocking(move || Reader::new(path))
+            .await
+            .map_err(io::Error::from)??;
+        Ok(reader.into())
+    }
+
+    /// Sets the number of messages buffered ahead of the consumer of a stream.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Returns a stream of the messages selected by `filter`, in the order of
+    /// [`Reader::messages`].
+    ///
+    /// The messages are read on the blocking thread pool, so the returned future must be
+    /// awaited within a tokio runtime. Errors are yielded as items. Opening the selected
+    /// messages fails before the first message, a failed read ends the stream after its
+    /// error.
+    pub async fn messages(
+        &self,
+        filter: ReadFilter,
+    ) -> impl Stream<Item = Result<Message>> + Send + Unpin + 'static {
+        let (sender, receiver) = mpsc::channel(self.capacity);
+        let reader = Arc::clone(&self.reader);
+        tokio::task::spawn_blocking(move || {
+            // the reader is only read from, a panic of another stream does not corrupt it
+            let reader = match reader.lock().unwrap_or_else(|e| e.into_inner()).try_clone() {
+                Ok(reader) => reader,
+                Err(e) => {
+                    let _ = sender.blocking_send(Err(e));
+                    return;
+                }
+            };
+            let messages = match reader.messages(&filter) {
+                Ok(messages) => messages,
+                Err(e) => {
+                    let _ = sender.blocking_send(Err(e));
+                    return;
+                }
+            };
+            for message in messages {
+                // a send fails once the stream was dropped
+                if sender.blocking_send(message).is_err() {
+                    return;
+                }
+            }
+        });
+        ReceiverStream::new(receiver)
+    }
+}
+
+impl From<Reader> for AsyncReader {
+    fn from(reader: Reader) -> Self {
+        AsyncReader {
+            metadata: reader.metadata.clone(),
+            connections: reader.connections.clone(),
+            reader: Arc::new(Mutex::new(reader)),
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use crate::*;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// How the data of a bag was compressed by rosbag2, see `Metadata::compression_mode`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionMode {
+    None,
+    /// Every database file is compressed as a whole (`<name>.db3.zstd`).
+    File,
+    /// The data of every message is compressed on its own.
+    Message,
+}
+
+impl CompressionMode {
+    /// Parses the compression mode and format of the metadata.
+    ///
+    /// rosbag2 writes the mode in upper case and an empty string for uncompressed bags.
+    /// Only the `zstd` format is supported.
+    pub fn from_metadata(mode: &str, format: &str) -> Result<Self> {
+        let mode = match mode.to_lowercase().as_str() {
+            "" | "none" => return Ok(CompressionMode::None),
+            "file" => CompressionMode::File,
+            "message" => CompressionMode::Message,
+            _ => return Err(Rosbag2Error::UnsupportedCompression(mode.to_string())),
+        };
+
+        if format != "zstd" {
+            return Err(Rosbag2Error::UnsupportedCompression(format!(
+                "{format} (only zstd is supported)"
+            )));
+        }
+        Ok(mode)
+    }
+}
+
+/// Decompresses the data of a message of a message compressed bag.
+pub fn decompress_message(data: &[u8]) -> Result<Vec<u8>> {
+    Ok(zstd::decode_all(data)?)
+}
+
+/// Decompresses the data of a message of a message compressed bag, appending it to
+/// `buffer`.
+pub fn decompress_message_into(data: &[u8], buffer: &mut Vec<u8>) -> Result<()> {
+    zstd::stream::copy_decode(data, buffer)?;
+    Ok(())
+}
+
+/// Decompresses a zstd compressed database file into `target_dir` and returns the path
+/// of the decompressed file, named like the source without its `.zstd` extension.
+pub fn decompress_file(source: &Path, target_dir: &Path) -> Result<PathBuf> {
+    let name = source
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let target = target_dir.join(name.strip_suffix(".zstd").unwrap_or(&name));
+
+    let mut reader = File::open(source)?;
+    let mut writer = File::create(&target)?;
+    zstd::stream::copy_decode(&mut reader, &mut writer)?;
+    Ok(target)
+}
@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+
+/// Errors returned by this crate.
+#[derive(Debug, thiserror::Error)]
+pub enum Rosbag2Error {
+    /// Neither metadata.yaml nor any database file was found in the bag directory.
+    #[error("No metadata.yaml and no .db3 files found in {0:?}")]
+    MissingMetadata(PathBuf),
+
+    #[error("Not supported version: {0}")]
+    UnsupportedVersion(i32),
+
+    #[error("Not supported storage identifier: {0}")]
+    UnsupportedStorage(String),
+
+    #[error("Not supported compression: {0}")]
+    UnsupportedCompression(String),
+
+    #[error("Only CDR serialization format is supported: {0:?}")]
+    UnsupportedSerialization(String),
+
+    /// A database file does not have the tables or columns of a rosbag2 database.
+    #[error("Database schema mismatch: {0}")]
+    SchemaMismatch(String),
+
+    #[error("Tried to write to unknown connection {id} on topic {topic}")]
+    UnknownConnection { id: i32, topic: String },
+
+    #[error("Connection can only be added once: {topic} ({msgtype})")]
+    DuplicateConnection { topic: String, msgtype: String },
+
+    /// The contents of a different number of database files than listed by the metadata
+    /// were given.
+    #[error("Metadata lists {expected} database files, {found} were given")]
+    FileCount { expected: usize, found: usize },
+
+    /// The file index does not refer to a database file of the bag.
+    #[error("No database file with index {0}")]
+    UnknownFile(usize),
+
+    /// The operation needs a bag with a single database file, the bag has this many.
+    #[error("Only supported for a single database file, the bag has {0}")]
+    MultipleFiles(usize),
+
+    #[error("Bag was not opened.")]
+    NotOpen,
+
+    #[error("Database file {0:?} already exists.")]
+    AlreadyExists(PathBuf),
+
+    #[error("Invalid metadata: {0}")]
+    Metadata(#[from] serde_yaml::Error),
+
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// An error returned by a user-provided message handler.
+    #[error("Message handler failed: {0}")]
+    Handler(Box<dyn std::error::Error + Send + Sync>),
+}
+
+pub type Result<T, E = Rosbag2Error> = std::result::Result<T, E>;
@@ -0,0 +1,236 @@
+//! Reading a bag while it is still being recorded.
+
+use crate::*;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Maximum number of rows read from a database file per poll.
+const POLL_ROWS: i64 = 256;
+
+/// How [`Follow`] waits for new messages.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FollowOptions {
+    /// Time to wait between two polls that found nothing new.
+    pub poll_interval: Duration,
+    /// Stop following when nothing new was found for this long. `None` waits until the
+    /// bag is closed.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for FollowOptions {
+    fn default() -> Self {
+        FollowOptions {
+            poll_interval: Duration::from_millis(100),
+            timeout: None,
+        }
+    }
+}
+
+/// Iterator over the messages of a bag that is still being recorded, see
+/// [`Reader::follow`].
+///
+/// The database files of the bag directory are polled for rows added since the last
+/// poll, and split files are picked up as they are created. The bag counts as closed
+/// once its metadata.yaml exists, which rosbag2 and [`Writer`] write when closing it;
+/// the iterator then returns the remaining messages and ends.
+///
+/// Messages are returned in the order they were written, file by file, and connection
+/// ids are assigned in the order topics are discovered. The connections of messages are
+/// those at their discovery, [`Follow::connections`] has the message counts. The data of
+/// message compressed bags is returned as stored.
+pub struct Follow {
+    path: PathBuf,
+    filter: ReadFilter,
+    options: FollowOptions,
+    connections: Vec<TopicConnection>,
+    /// The connections handed out with messages, by index into `connections`.
+    message_connections: Vec<Arc<TopicConnection>>,
+    files: Vec<FollowedFile>,
+    buffer: VecDeque<Message>,
+    /// Last time a poll found anything new.
+    last_activity: Instant,
+    closed: bool,
+    done: bool,
+}
+
+struct FollowedFile {
+    name: String,
+    tail: Sqlite3Tail,
+    /// Maps topic ids of the file to indices into `Follow::connections`.
+    topics: HashMap<i64, usize>,
+}
+
+impl Follow {
+    pub fn new(path: impl Into<PathBuf>, filter: ReadFilter, options: FollowOptions) -> Self {
+        Follow {
+            path: path.into(),
+            filter,
+            options,
+            connections: vec![],
+            message_connections: vec![],
+            files: vec![],
+            buffer: VecDeque::new(),
+            last_activity: Instant::now(),
+            closed: false,
+            done: false,
+        }
+    }
+
+    /// Connections discovered so far, with the number of their messages read so far.
+    pub fn connections(&self) -> &[TopicConnection] {
+        &self.connections
+    }
+
+    /// Returns whether following ended because the bag was closed rather than because
+    /// of the timeout.
+    pub fn closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Reads the next rows of the first file that has any and returns whether anything
+    /// new was found.
+    fn poll(&mut self) -> Result<bool> {
+        let found = self.open_new_files()?;
+
+        for file in &mut self.files {
+            for topic in file.tail.new_topics()? {
+                // connections of other files are merged, those of one file kept apart
+                let index = match self.connections.iter().enumerate().position(|(index, c)| {
+                    c.topic == topic.topic
+                        && c.msgtype == topic.msgtype
+                        && !file.topics.values().any(|&known| known == index)
+                }) {
+                    Some(index) => index,
+                    None => {
+                        let connection = TopicConnection {
+                            id: self.connections.len() as i32 + 1,
+                            ..topic.clone()
+                        };
+                        self.message_connections.push(Arc::new(connection.clone()));
+                        self.connections.push(connection);
+                        self.connections.len() - 1
+                    }
+                };
+                file.topics.insert(topic.id as i64, index);
+            }
+
+            let selected = file
+                .topics
+                .iter()
+                .filter(|(_, &index)| self.filter.matches(&self.connections[index]))
+                .map(|(&id, _)| id)
+                .collect::<Vec<_>>();
+            let rows = file.tail.new_messages(
+                &selected,
+                self.filter.start.map(Time::as_nanos),
+                self.filter.stop.map(Time::as_nanos),
+                POLL_ROWS,
+            )?;
+            if rows.is_empty() {
+                continue;
+            }
+
+            for (topic_id, timestamp, data) in rows {
+                // like the reader, skip messages of topic ids missing from the topics table
+                let Some(&index) = file.topics.get(&topic_id) else {
+                    warn!(
+                        file = file.name,
+                        topic_id, "skipping message of unknown topic"
+                    );
+                    continue;
+                };
+                self.connections[index].msgcount += 1;
+                if let Some(data) = data {
+                    self.buffer.push_back(Message {
+                        connection: Arc::clone(&self.message_connections[index]),
+                        timestamp: Time(timestamp),
+                        publish_timestamp: None,
+                        data,
+                    });
+                }
+            }
+            // later files are only read once this one has no new rows
+            return Ok(true);
+        }
+        Ok(found)
+    }
+
+    /// Opens the database files created since the last poll and returns whether there
+    /// were any.
+    fn open_new_files(&mut self) -> Result<bool> {
+        let names = match db3_files(&self.path) {
+            Ok(names) => names,
+            // the recorder has not created the bag directory yet
+            Err(Rosbag2Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+
+        let mut found = false;
+        for name in names {
+            if self.files.iter().any(|file| file.name == name) {
+                continue;
+            }
+            let tail = match Sqlite3Tail::open(&self.path.join(&name)) {
+                Ok(tail) => tail,
+                // the file is still being set up, keep the order of the files and retry
+                Err(Rosbag2Error::SchemaMismatch(_)) => break,
+                Err(e) => return Err(e),
+            };
+            debug!(file = name, "following database file");
+            self.files.push(FollowedFile {
+                name,
+                tail,
+                topics: HashMap::new(),
+            });
+            found = true;
+        }
+        Ok(found)
+    }
+}
+
+impl Iterator for Follow {
+    type Item = Result<Message>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(message) = self.buffer.pop_front() {
+                return Some(Ok(message));
+            }
+            if self.done {
+                return None;
+            }
+
+            // checked before polling, so that the poll sees everything written before
+            let closed = self.path.join("metadata.yaml").exists();
+            match self.poll() {
+                Ok(true) => {
+                    self.last_activity = Instant::now();
+                    continue;
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+
+            if closed {
+                self.closed = true;
+                self.done = true;
+            } else if self
+                .options
+                .timeout
+                .is_some_and(|timeout| self.last_activity.elapsed() >= timeout)
+            {
+                self.done = true;
+            } else {
+                thread::sleep(self.options.poll_interval);
+            }
+        }
+    }
+}
@@ -1,58 +1,82 @@
-///
-/// # Usage
-///
-/// ```
-/// use std::rc::Rc;
-/// use std::cell::RefCell;
-/// use rosbag2_rs::{Reader, Writer};
-/// use anyhow::Result;
-/// use tempfile::tempdir;
-
-/// fn main() -> Result<()> {
-///     let dir = tempdir()?;
-///     let mut writer = Writer::new(dir.path());
-///     writer.open()?;
-///
-///     let connection = writer.add_connection("topic1", "msgtype1", "cdr", "")?;
-///     for i in 0..10 {
-///         writer.write(&connection, i as i64, &[i * 2 + 1 as u8])?;
-///     }
-///     let connection = writer.add_connection("topic2", "msgtype2", "cdr", "")?;
-///     for i in 0..10 {
-///         writer.write(&connection, i as i64, &[i * 2 as u8])?;
-///     }
-///     writer.close()?;
-///
-///     let mut reader = Reader::new(dir.path())?;
-///     let msg_data: Vec<(i64, i64, Vec<u8>)> = vec![];
-///     let msg_data = Rc::new(RefCell::new(msg_data)); // Wrap the vector in Rc and RefCell
-///     reader.handle_messages(
-///         |(id, timestamp, data)| {
-///             // Use `borrow_mut` to get a mutable reference to the vector
-///             println!("processed message: {:?} {:?} {:?}", id, timestamp, data);
-///
-///             msg_data.borrow_mut().push((id, timestamp, data));
-///             Ok(())
-///         },
-///         None,
-///         None,
-///     )?;
-///
-///     Ok(())
-/// }
-/// ```
+//!
+//! # Usage
+//!
+//! ```
+//! use rosbag2_rs::{ReadFilter, Reader, Writer};
+//! use anyhow::Result;
+//! use tempfile::tempdir;
+//!
+//! fn main() -> Result<()> {
+//!     let dir = tempdir()?;
+//!     let mut writer = Writer::new(dir.path());
+//!     writer.open()?;
+//!
+//!     let connection = writer.add_connection("topic1", "msgtype1", "cdr", "")?;
+//!     for i in 0..10 {
+//!         writer.write(&connection, i as i64, &[i * 2 + 1 as u8])?;
+//!     }
+//!     let connection = writer.add_connection("topic2", "msgtype2", "cdr", "")?;
+//!     for i in 0..10 {
+//!         writer.write(&connection, i as i64, &[i * 2 as u8])?;
+//!     }
+//!     writer.close()?;
+//!
+//!     let reader = Reader::new(dir.path())?;
+//!     let mut msg_data = vec![];
+//!     for message in reader.messages(&ReadFilter::default())? {
+//!         let message = message?;
+//!         println!(
+//!             "processed message: {} {:?} {:?}",
+//!             message.connection.topic, message.timestamp, message.data
+//!         );
+//!         msg_data.push(message);
+//!     }
+//!     assert_eq!(msg_data.len(), 20);
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use std::sync::Arc;
+
+pub mod error;
+pub use error::*;
+
+pub mod compression;
+pub use compression::*;
+
 pub mod metadata;
 pub use metadata::*;
 
+pub mod qos;
+pub use qos::*;
+
+pub mod time;
+pub use time::*;
+
 pub mod sqlite3_storage;
 pub use sqlite3_storage::*;
 
 pub mod reader;
 pub use reader::*;
 
+pub mod follow;
+pub use follow::*;
+
+pub mod statistics;
+pub use statistics::*;
+
+pub mod reindex;
+pub use reindex::*;
+
 pub mod writer;
 pub use writer::*;
 
+#[cfg(feature = "tokio")]
+pub mod async_reader;
+#[cfg(feature = "tokio")]
+pub use async_reader::*;
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct TopicConnection {
     pub id: i32,
@@ -64,22 +88,34 @@ pub struct TopicConnection {
     pub ext: ConnectionExt,
 }
 
+impl TopicConnection {
+    /// Parses the QoS profiles the topic was offered with.
+    pub fn qos_profiles(&self) -> Result<Vec<QosProfile>> {
+        QosProfile::parse_list(&self.ext.offered_qos_profiles)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct ConnectionExt {
     pub serialization_format: String,
     pub offered_qos_profiles: String,
+    /// RIHS hash of the message type description, empty for bags older than Iron.
+    pub type_description_hash: String,
     // Add other fields specific to ROS bag version 2
 }
 
+/// All connections of a topic, see [`Reader::topics`].
 #[derive(Clone, Debug)]
 pub struct TopicInfo {
-    pub msgtype: String,
+    /// Message type shared by the connections, `None` if their types differ.
+    pub msgtype: Option<String>,
+    /// Messages on all connections of the topic.
     pub msgcount: i32,
     pub connections: Vec<TopicConnection>,
 }
 
 impl TopicInfo {
-    pub fn new(msgtype: String, msgcount: i32, connections: Vec<TopicConnection>) -> Self {
+    pub fn new(msgtype: Option<String>, msgcount: i32, connections: Vec<TopicConnection>) -> Self {
         TopicInfo {
             msgtype,
             msgcount,
@@ -87,3 +123,67 @@ impl TopicInfo {
         }
     }
 }
+
+/// Address of a message in the database files of a bag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MessageId {
+    /// Index of the database file in `Metadata::relative_file_paths`.
+    pub file: usize,
+    /// Row id of the message in the `messages` table of the file.
+    pub row: i64,
+}
+
+/// A message without its data, see [`Reader::scan_messages`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct MessageInfo {
+    pub id: MessageId,
+    /// Connection the message was recorded on.
+    pub connection: Arc<TopicConnection>,
+    /// Time the recorder received the message.
+    pub timestamp: Time,
+    /// Size of the data as stored, i.e. compressed for message compressed bags.
+    pub size: usize,
+}
+
+/// A message read from a bag, see [`Reader::messages`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    /// Connection the message was recorded on.
+    pub connection: Arc<TopicConnection>,
+    /// Time the recorder received the message.
+    pub timestamp: Time,
+    /// Time the message was published. sqlite3 bags only store the receive time, so this
+    /// is `None` for them.
+    pub publish_timestamp: Option<Time>,
+    pub data: Vec<u8>,
+}
+
+/// A message whose data is borrowed from storage, see [`Reader::for_each_message`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MessageRef<'a> {
+    /// Connection the message was recorded on.
+    pub connection: &'a TopicConnection,
+    /// Time the recorder received the message.
+    pub timestamp: Time,
+    /// Time the message was published, see [`Message::publish_timestamp`].
+    pub publish_timestamp: Option<Time>,
+    pub data: &'a [u8],
+}
+
+/// Tells [`Reader::handle_messages`] whether to go on after a message was handled.
+///
+/// Handlers that always continue can return `Ok(())`, which converts to `Continue`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HandlerAction {
+    /// Hand the next message to the handler.
+    #[default]
+    Continue,
+    /// Stop reading and return `Ok(())` to the caller.
+    Stop,
+}
+
+impl From<()> for HandlerAction {
+    fn from(_: ()) -> Self {
+        HandlerAction::Continue
+    }
+}
@@ -1,34 +1,61 @@
-use serde::{Deserialize, Serialize};
+//! Model of the rosbag2 `metadata.yaml` file.
+//!
+//! rosbag2 has extended the file over time, the version number tells which fields are
+//! present (see `metadata_io.cpp` of rosbag2):
+//!
+//! - version 3 adds `compression_format` and `compression_mode`,
+//! - version 4 adds the `offered_qos_profiles` of every topic,
+//! - version 5 adds the per-file information in `files`,
+//! - version 6 adds `custom_data`,
+//! - version 7 adds the `type_description_hash` of every topic,
+//! - version 8 adds `ros_distro`,
+//! - version 9 stores `offered_qos_profiles` as a YAML sequence instead of a string.
+//!
+//! Fields missing from older versions are deserialized to their defaults. Serialization
+//! writes exactly the fields of `Metadata::version`, so a bag can be written back in any
+//! supported version by changing that field. QoS profiles are re-encoded for the distro
+//! of that version, see [`QosFormat`].
+
+use crate::qos::{QosFormat, QosProfile};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
 
-#[derive(Serialize, Deserialize)]
+/// Latest metadata version this crate can read and write.
+pub const LATEST_METADATA_VERSION: i32 = 9;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct StartingTime {
     pub nanoseconds_since_epoch: i64,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct BagDuration {
     pub nanoseconds: i64,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TopicMetadata {
     pub name: String,
 
     #[serde(rename = "type")]
     pub type_: String, // `type` is a reserved keyword in Rust
+    #[serde(default)]
     pub serialization_format: String,
+    /// QoS profiles as a YAML string, also when they were stored as a sequence (version 9).
+    #[serde(default, deserialize_with = "deserialize_qos_profiles")]
     pub offered_qos_profiles: String,
-    // pub type_description_hash: String // TODO: humble rosbag2 does not need this field
+    #[serde(default)]
+    pub type_description_hash: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TopicWithMessageCount {
     pub message_count: i32,
     pub topic_metadata: TopicMetadata,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct FileInformation {
     pub path: String,
     pub starting_time: StartingTime,
@@ -36,7 +63,7 @@ pub struct FileInformation {
     pub message_count: i32,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct Metadata {
     pub version: i32,
     pub storage_identifier: String,
@@ -44,11 +71,16 @@ pub struct Metadata {
     pub starting_time: StartingTime,
     pub duration: BagDuration,
     pub message_count: i32,
+    #[serde(default)]
     pub compression_format: String,
+    #[serde(default)]
     pub compression_mode: String,
     pub topics_with_message_count: Vec<TopicWithMessageCount>,
+    #[serde(default)]
     pub files: Vec<FileInformation>,
+    #[serde(default, deserialize_with = "deserialize_null_as_default")]
     pub custom_data: HashMap<String, String>,
+    #[serde(default)]
     pub ros_distro: String,
 }
 
@@ -56,3 +88,106 @@ pub struct Metadata {
 pub struct BagFileInfo {
     pub rosbag2_bagfile_information: Metadata,
 }
+
+impl Serialize for Metadata {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let version = self.version;
+        let topics = self
+            .topics_with_message_count
+            .iter()
+            .map(|topic| VersionedTopic { version, topic })
+            .collect::<Vec<_>>();
+
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("version", &version)?;
+        map.serialize_entry("storage_identifier", &self.storage_identifier)?;
+        map.serialize_entry("duration", &self.duration)?;
+        map.serialize_entry("starting_time", &self.starting_time)?;
+        map.serialize_entry("message_count", &self.message_count)?;
+        map.serialize_entry("topics_with_message_count", &topics)?;
+        if version >= 3 {
+            map.serialize_entry("compression_format", &self.compression_format)?;
+            map.serialize_entry("compression_mode", &self.compression_mode)?;
+        }
+        map.serialize_entry("relative_file_paths", &self.relative_file_paths)?;
+        if version >= 5 {
+            map.serialize_entry("files", &self.files)?;
+        }
+        if version >= 6 {
+            map.serialize_entry("custom_data", &self.custom_data)?;
+        }
+        if version >= 8 {
+            map.serialize_entry("ros_distro", &self.ros_distro)?;
+        }
+        map.end()
+    }
+}
+
+/// Serializes a topic with the fields of the given metadata version.
+struct VersionedTopic<'a> {
+    version: i32,
+    topic: &'a TopicWithMessageCount,
+}
+
+impl Serialize for VersionedTopic<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let topic = &self.topic.topic_metadata;
+
+        let mut topic_metadata = serde_yaml::Mapping::new();
+        topic_metadata.insert("name".into(), topic.name.clone().into());
+        topic_metadata.insert("type".into(), topic.type_.clone().into());
+        topic_metadata.insert(
+            "serialization_format".into(),
+            topic.serialization_format.clone().into(),
+        );
+        // profiles that cannot be parsed are written as read
+        let profiles = QosProfile::parse_list(&topic.offered_qos_profiles)
+            .and_then(|profiles| {
+                QosProfile::format_list(&profiles, QosFormat::for_metadata_version(self.version))
+            })
+            .unwrap_or_else(|_| topic.offered_qos_profiles.clone());
+        if self.version >= 9 {
+            let profiles = if profiles.trim().is_empty() {
+                serde_yaml::Value::Sequence(vec![])
+            } else {
+                serde_yaml::from_str(&profiles).map_err(serde::ser::Error::custom)?
+            };
+            topic_metadata.insert("offered_qos_profiles".into(), profiles);
+        } else if self.version >= 4 {
+            topic_metadata.insert("offered_qos_profiles".into(), profiles.into());
+        }
+        if self.version >= 7 {
+            topic_metadata.insert(
+                "type_description_hash".into(),
+                topic.type_description_hash.clone().into(),
+            );
+        }
+
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("topic_metadata", &topic_metadata)?;
+        map.serialize_entry("message_count", &self.topic.message_count)?;
+        map.end()
+    }
+}
+
+/// Accepts QoS profiles both as a YAML string (up to version 8) and as a YAML sequence
+/// (version 9), and returns them as a YAML string.
+fn deserialize_qos_profiles<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    match serde_yaml::Value::deserialize(deserializer)? {
+        serde_yaml::Value::Null => Ok(String::new()),
+        serde_yaml::Value::String(profiles) => Ok(profiles),
+        serde_yaml::Value::Sequence(profiles) if profiles.is_empty() => Ok(String::new()),
+        profiles => serde_yaml::to_string(&profiles).map_err(serde::de::Error::custom),
+    }
+}
+
+/// rosbag2 writes empty maps such as `custom_data` as `~`.
+fn deserialize_null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
@@ -0,0 +1,315 @@
+//! Typed model of the QoS profiles rosbag2 stores for every topic.
+//!
+//! rosbag2 stores the profiles a topic was offered with as a YAML sequence. Up to Iron
+//! (metadata version 8) the policies are written as the integers of the rmw enums and
+//! infinite durations as `{sec: 2147483647, nsec: 4294967295}`. Jazzy (metadata version 9)
+//! writes the policy names and `{sec: 9223372036, nsec: 854775807}` instead. Both are
+//! accepted when parsing, [`QosFormat`] selects the one to write.
+
+use crate::*;
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+
+/// Infinite duration as written up to Iron.
+const HUMBLE_INFINITE: (i64, i64) = (2147483647, 4294967295);
+
+/// Infinite duration as written by Jazzy, `RMW_DURATION_INFINITE`.
+const JAZZY_INFINITE: (i64, i64) = (9223372036, 854775807);
+
+macro_rules! qos_policy {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident = ($int:expr, $str:expr)),+ $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($variant),+
+        }
+
+        impl $name {
+            /// Value of the policy in the rmw enum, as written up to Iron.
+            pub fn as_int(self) -> i64 {
+                match self {
+                    $($name::$variant => $int),+
+                }
+            }
+
+            /// Name of the policy, as written by Jazzy.
+            pub fn as_str(self) -> &'static str {
+                match self {
+                    $($name::$variant => $str),+
+                }
+            }
+
+            fn from_yaml(value: &Value) -> Option<Self> {
+                match value {
+                    Value::Number(number) => {
+                        let number = number.as_i64()?;
+                        [$($name::$variant),+].into_iter().find(|p| p.as_int() == number)
+                    }
+                    Value::String(name) => {
+                        [$($name::$variant),+].into_iter().find(|p| p.as_str() == name)
+                    }
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+qos_policy! {
+    /// Which samples are kept for late joining subscriptions.
+    HistoryPolicy {
+        SystemDefault = (0, "system_default"),
+        KeepLast = (1, "keep_last"),
+        KeepAll = (2, "keep_all"),
+        Unknown = (3, "unknown"),
+    }
+}
+
+qos_policy! {
+    /// Whether delivery of samples is guaranteed.
+    ReliabilityPolicy {
+        SystemDefault = (0, "system_default"),
+        Reliable = (1, "reliable"),
+        BestEffort = (2, "best_effort"),
+        Unknown = (3, "unknown"),
+        BestAvailable = (4, "best_available"),
+    }
+}
+
+qos_policy! {
+    /// Whether samples are kept for subscriptions that join later.
+    DurabilityPolicy {
+        SystemDefault = (0, "system_default"),
+        TransientLocal = (1, "transient_local"),
+        Volatile = (2, "volatile"),
+        Unknown = (3, "unknown"),
+        BestAvailable = (4, "best_available"),
+    }
+}
+
+qos_policy! {
+    /// How the liveliness of a publisher is asserted.
+    LivelinessPolicy {
+        SystemDefault = (0, "system_default"),
+        Automatic = (1, "automatic"),
+        ManualByNode = (2, "manual_by_node"),
+        ManualByTopic = (3, "manual_by_topic"),
+        Unknown = (4, "unknown"),
+        BestAvailable = (5, "best_available"),
+    }
+}
+
+/// Encoding of QoS profiles in bag files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QosFormat {
+    /// Policy integers, written up to Iron.
+    Humble,
+    /// Policy names, written by Jazzy.
+    Jazzy,
+}
+
+impl QosFormat {
+    /// Format of the profiles in metadata of the given version.
+    pub fn for_metadata_version(version: i32) -> Self {
+        if version >= 9 {
+            QosFormat::Jazzy
+        } else {
+            QosFormat::Humble
+        }
+    }
+}
+
+/// A QoS profile a topic was offered with, see `rmw_qos_profile_t`.
+///
+/// Durations are `None` when infinite and zero when unspecified.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QosProfile {
+    pub history: HistoryPolicy,
+    pub depth: u32,
+    pub reliability: ReliabilityPolicy,
+    pub durability: DurabilityPolicy,
+    pub deadline: Option<Duration>,
+    pub lifespan: Option<Duration>,
+    pub liveliness: LivelinessPolicy,
+    pub liveliness_lease_duration: Option<Duration>,
+    pub avoid_ros_namespace_conventions: bool,
+}
+
+impl Default for QosProfile {
+    /// `rmw_qos_profile_default`: keep the last 10 samples, reliable and volatile.
+    fn default() -> Self {
+        QosProfile {
+            history: HistoryPolicy::KeepLast,
+            depth: 10,
+            reliability: ReliabilityPolicy::Reliable,
+            durability: DurabilityPolicy::Volatile,
+            deadline: Some(Duration(0)),
+            lifespan: Some(Duration(0)),
+            liveliness: LivelinessPolicy::SystemDefault,
+            liveliness_lease_duration: Some(Duration(0)),
+            avoid_ros_namespace_conventions: false,
+        }
+    }
+}
+
+impl QosProfile {
+    /// Profile of a latched publisher (such as `/tf_static`) as recorded by rosbag2:
+    /// reliable and transient local, with infinite durations.
+    pub fn latched() -> Self {
+        QosProfile {
+            history: HistoryPolicy::Unknown,
+            depth: 0,
+            reliability: ReliabilityPolicy::Reliable,
+            durability: DurabilityPolicy::TransientLocal,
+            deadline: None,
+            lifespan: None,
+            liveliness: LivelinessPolicy::Automatic,
+            liveliness_lease_duration: None,
+            avoid_ros_namespace_conventions: false,
+        }
+    }
+
+    /// Parses the profiles of a topic in either format. An empty string has no profiles.
+    pub fn parse_list(yaml: &str) -> Result<Vec<QosProfile>> {
+        if yaml.trim().is_empty() {
+            return Ok(vec![]);
+        }
+        let raw: Vec<RawProfile> = serde_yaml::from_str(yaml)?;
+        raw.into_iter()
+            .map(|raw| raw.into_profile().map_err(Rosbag2Error::from))
+            .collect()
+    }
+
+    /// Writes `profiles` as a YAML sequence in the given format, as stored in the
+    /// `topics` table and metadata.yaml.
+    pub fn format_list(profiles: &[QosProfile], format: QosFormat) -> Result<String> {
+        if profiles.is_empty() {
+            return Ok(String::new());
+        }
+        let profiles = profiles
+            .iter()
+            .map(|profile| profile.to_yaml(format))
+            .collect::<Vec<_>>();
+        Ok(serde_yaml::to_string(&profiles)?)
+    }
+
+    fn to_yaml(&self, format: QosFormat) -> Value {
+        let policy = |int: i64, name: &str| match format {
+            QosFormat::Humble => Value::from(int),
+            QosFormat::Jazzy => Value::from(name),
+        };
+        let duration = |duration: Option<Duration>| {
+            let (sec, nsec) = match (duration, format) {
+                (Some(duration), _) => (
+                    duration.0.div_euclid(NANOS_PER_SEC),
+                    duration.0.rem_euclid(NANOS_PER_SEC),
+                ),
+                (None, QosFormat::Humble) => HUMBLE_INFINITE,
+                (None, QosFormat::Jazzy) => JAZZY_INFINITE,
+            };
+            let mut map = Mapping::new();
+            map.insert("sec".into(), sec.into());
+            map.insert("nsec".into(), nsec.into());
+            Value::Mapping(map)
+        };
+
+        let mut map = Mapping::new();
+        map.insert(
+            "history".into(),
+            policy(self.history.as_int(), self.history.as_str()),
+        );
+        map.insert("depth".into(), self.depth.into());
+        map.insert(
+            "reliability".into(),
+            policy(self.reliability.as_int(), self.reliability.as_str()),
+        );
+        map.insert(
+            "durability".into(),
+            policy(self.durability.as_int(), self.durability.as_str()),
+        );
+        map.insert("deadline".into(), duration(self.deadline));
+        map.insert("lifespan".into(), duration(self.lifespan));
+        map.insert(
+            "liveliness".into(),
+            policy(self.liveliness.as_int(), self.liveliness.as_str()),
+        );
+        map.insert(
+            "liveliness_lease_duration".into(),
+            duration(self.liveliness_lease_duration),
+        );
+        map.insert(
+            "avoid_ros_namespace_conventions".into(),
+            self.avoid_ros_namespace_conventions.into(),
+        );
+        Value::Mapping(map)
+    }
+}
+
+/// A profile as written in either format.
+#[derive(Deserialize)]
+struct RawProfile {
+    history: Value,
+    #[serde(default)]
+    depth: u32,
+    reliability: Value,
+    durability: Value,
+    deadline: RawDuration,
+    lifespan: RawDuration,
+    liveliness: Value,
+    liveliness_lease_duration: RawDuration,
+    #[serde(default)]
+    avoid_ros_namespace_conventions: bool,
+}
+
+#[derive(Deserialize)]
+struct RawDuration {
+    sec: i64,
+    nsec: i64,
+}
+
+impl RawProfile {
+    fn into_profile(self) -> Result<QosProfile, serde_yaml::Error> {
+        fn policy<T>(
+            value: &Value,
+            parse: fn(&Value) -> Option<T>,
+            name: &str,
+        ) -> Result<T, serde_yaml::Error> {
+            parse(value).ok_or_else(|| {
+                serde::de::Error::custom(format!("invalid {name} policy: {value:?}"))
+            })
+        }
+
+        Ok(QosProfile {
+            history: policy(&self.history, HistoryPolicy::from_yaml, "history")?,
+            depth: self.depth,
+            reliability: policy(
+                &self.reliability,
+                ReliabilityPolicy::from_yaml,
+                "reliability",
+            )?,
+            durability: policy(&self.durability, DurabilityPolicy::from_yaml, "durability")?,
+            deadline: self.deadline.into_duration()?,
+            lifespan: self.lifespan.into_duration()?,
+            liveliness: policy(&self.liveliness, LivelinessPolicy::from_yaml, "liveliness")?,
+            liveliness_lease_duration: self.liveliness_lease_duration.into_duration()?,
+            avoid_ros_namespace_conventions: self.avoid_ros_namespace_conventions,
+        })
+    }
+}
+
+impl RawDuration {
+    fn into_duration(self) -> Result<Option<Duration>, serde_yaml::Error> {
+        // both infinity encodings and anything beyond them
+        if self.sec >= HUMBLE_INFINITE.0 {
+            return Ok(None);
+        }
+        if self.sec < 0 || u32::try_from(self.nsec).is_err() {
+            return Err(serde::de::Error::custom(format!(
+                "invalid duration: {} s {} ns",
+                self.sec, self.nsec
+            )));
+        }
+        // seconds below the Humble infinity keep the nanoseconds well within i64
+        Ok(Some(Duration(self.sec * NANOS_PER_SEC + self.nsec)))
+    }
+}
@@ -1,15 +1,64 @@
 use crate::*;
-use anyhow::{anyhow, Result};
-use std::collections::HashMap;
-use std::path::Path;
-use std::{fs, vec};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{fs, io, vec};
+use tempfile::TempDir;
+use tracing::{debug, debug_span, warn};
 
 // Define other structs like Metadata, FileInformation, Connection, etc.
 
-pub struct Reader {
-    pub metadata: Metadata,
-    pub connections: Vec<TopicConnection>,
-    storage: Sqlite3Reader,
+/// Selects which messages are returned by [`Reader::messages`].
+///
+/// The default filter selects every message in the bag. Each non-empty set narrows the
+/// selection down further, so a connection is read only if it matches all of them.
+///
+/// ```
+/// use rosbag2_rs::{ReadFilter, Time};
+///
+/// let filter = ReadFilter {
+///     topics: ["/imu".to_string()].into(),
+///     start: Some(Time(1_000_000_000)),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReadFilter {
+    /// Only messages of these topics are returned.
+    pub topics: HashSet<String>,
+    /// Only messages of the connections with these ids are returned.
+    pub connections: HashSet<i32>,
+    /// Only messages of these message types are returned.
+    pub msgtypes: HashSet<String>,
+    /// Only messages with `timestamp >= start` are returned.
+    pub start: Option<Time>,
+    /// Only messages with `timestamp < stop` are returned.
+    pub stop: Option<Time>,
+    /// Hand out the data of message compressed bags as stored, without decompressing it,
+    /// e.g. to copy it into another message compressed bag.
+    pub raw: bool,
+    /// Return the messages in descending timestamp order, latest first.
+    pub reverse: bool,
+}
+
+impl ReadFilter {
+    /// Returns whether messages of `connection` pass the topic, connection and message
+    /// type sets of this filter.
+    pub fn matches(&self, connection: &TopicConnection) -> bool {
+        (self.topics.is_empty() || self.topics.contains(&connection.topic))
+            && (self.connections.is_empty() || self.connections.contains(&connection.id))
+            && (self.msgtypes.is_empty() || self.msgtypes.contains(&connection.msgtype))
+    }
+
+    fn direction(&self) -> Direction {
+        if self.reverse {
+            Direction::Backward
+        } else {
+            Direction::Forward
+        }
+    }
 }
 
 /// The `Reader` struct provides an interface for reading message data from a ROS bag file.
@@ -17,7 +66,6 @@ pub struct Reader {
 /// The `Reader` initializes with the path to a ROS bag directory and reads metadata
 /// and message data from the storage. It supports filtering messages by time and handling
 /// each message through a user-defined function.
-
 ///
 /// # Errors
 ///
@@ -30,122 +78,617 @@ pub struct Reader {
 ///
 /// - This struct assumes that the ROS bag files are in `sqlite3` format.
 /// - The `handle_messages` method allows for processing of individual messages.
+/// - The `messages` method returns an iterator over the messages instead.
+/// - The `for_each_message` method hands out the message data without copying it.
+/// - The `scan_messages` method reads only timestamps and sizes, the data of single
+///   messages is loaded on demand with `load_payload` or `open_payload`.
+/// - The `statistics` method computes per-topic rates, gaps and sizes in SQL.
+/// - Bags split into several files are read as one, in global timestamp order.
+/// - Bags compressed with zstd in file mode are decompressed to a temporary directory,
+///   the messages of bags compressed in message mode are decompressed as they are read.
+/// - Bags without metadata.yaml are opened with the metadata stored in or reconstructed
+///   from their database files.
+/// - A single `.db3` file can be opened on its own, see [`Reader::open_file`].
+pub struct Reader {
+    pub metadata: Metadata,
+    pub connections: Vec<TopicConnection>,
+    /// The connections handed out with messages, by id.
+    message_connections: HashMap<i64, Arc<TopicConnection>>,
+    storage: Sqlite3Reader,
+    compression: CompressionMode,
+    /// Holds the decompressed database files of a file compressed bag while it is read,
+    /// shared with the readers returned by [`Reader::try_clone`].
+    decompressed: Option<Arc<TempDir>>,
+}
 
 impl Reader {
+    /// Opens the bag directory at `path`, or the database file at `path` like
+    /// [`Reader::open_file`].
     pub fn new(path: impl AsRef<Path>) -> Result<Self> {
-        let path = path.as_ref().to_path_buf();
+        let path = path.as_ref();
+        let _span = debug_span!("open", path = %path.display()).entered();
+        if path.is_file() {
+            return Self::open_file(path);
+        }
         let metapath = path.join("metadata.yaml");
 
-        let metadata_contents = fs::read_to_string(metapath).unwrap();
-        let bag_info: BagFileInfo = serde_yaml::from_str(&metadata_contents).unwrap();
+        let metadata = match fs::read_to_string(metapath) {
+            Ok(metadata_contents) => {
+                let bag_info: BagFileInfo = serde_yaml::from_str(&metadata_contents)?;
+                bag_info.rosbag2_bagfile_information
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Self::recover_metadata(path)?,
+            Err(e) => return Err(e.into()),
+        };
+        Self::with_metadata(path, metadata)
+    }
 
-        let metadata = bag_info.rosbag2_bagfile_information;
+    /// Opens a single `.db3` file on its own, without the bag directory around it.
+    ///
+    /// Any metadata.yaml next to the file is ignored. The metadata stored in the database
+    /// is used if it describes just this file, otherwise connections are read from the
+    /// `topics` table and counts and time bounds computed from the `messages` table.
+    pub fn open_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let _span = debug_span!("open_file", path = %path.display()).entered();
+        // report a missing file like a missing bag directory, SQLite would only fail to open it
+        fs::metadata(path)?;
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
 
-        // Check version and storage identifier
-        if metadata.version > 5 {
-            return Err(anyhow!("Not supported version: {}", metadata.version));
-        }
+        let mut storage = Sqlite3Reader::new(vec![path.to_string_lossy().into_owned()]);
+        storage.open()?;
+        let metadata = match storage.stored_metadata()? {
+            Some(metadata) if metadata.relative_file_paths == [name] => metadata,
+            _ => storage.reconstruct_metadata()?,
+        };
+        Self::with_metadata(path.parent().unwrap_or(Path::new("")), metadata)
+    }
 
-        if !metadata.compression_mode.is_empty() {
-            return Err(anyhow!(
-                "Not supported compression mode: {}",
-                metadata.compression_mode
-            ));
-        }
+    /// Reads a bag from the contents of its metadata.yaml and database files, without
+    /// touching the file system.
+    ///
+    /// `files` holds the contents of the files listed in `relative_file_paths` of the
+    /// metadata, in that order. Files of file compressed bags are decompressed in memory.
+    /// The bag is read like one opened with [`Reader::new`].
+    ///
+    /// ```no_run
+    /// # use rosbag2_rs::{ReadFilter, Reader};
+    /// # fn main() -> rosbag2_rs::Result<()> {
+    /// let metadata = std::fs::read_to_string("path/to/bag/metadata.yaml")?;
+    /// let file = std::fs::read("path/to/bag/bag_0.db3")?;
+    /// let reader = Reader::from_bytes(&metadata, vec![file])?;
+    /// let count = reader.messages(&ReadFilter::default())?.count();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_bytes(metadata_yaml: &str, files: Vec<Vec<u8>>) -> Result<Self> {
+        let _span = debug_span!("from_bytes").entered();
+        let bag_info: BagFileInfo = serde_yaml::from_str(metadata_yaml)?;
+        let metadata = bag_info.rosbag2_bagfile_information;
+        let compression = check_metadata(&metadata)?;
 
-        // metadata.topics_with_message_count.iter().map(|topi)
-        if let Some(topic_info) = metadata
-            .topics_with_message_count
-            .iter()
-            .find(|t| t.topic_metadata.serialization_format != "cdr")
-        {
-            return Err(anyhow!(
-                "Only CDR serialization format is supported: {:?}",
-                topic_info.topic_metadata.serialization_format
-            ));
+        let names = metadata.relative_file_paths.clone();
+        if files.len() != names.len() {
+            return Err(Rosbag2Error::FileCount {
+                expected: names.len(),
+                found: files.len(),
+            });
         }
+        let files = files
+            .into_iter()
+            .zip(&names)
+            .map(|(data, name)| {
+                if compression == CompressionMode::File && name.ends_with(".zstd") {
+                    // a compressed file is a single zstd frame, like the data of a message
+                    decompress_message(&data)
+                } else {
+                    Ok(data)
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
 
-        if metadata.storage_identifier != "sqlite3" {
-            return Err(anyhow!(
-                "Not supported storage identifier: {}",
-                metadata.storage_identifier
-            ));
-        }
+        let mut storage = Sqlite3Reader::new(names);
+        storage.open_bytes(files)?;
+        Self::with_storage(metadata, storage, compression, None)
+    }
 
-        // Initialize connections
-        let connections = metadata
-            .topics_with_message_count
-            .iter()
-            .enumerate()
-            .map(|(idx, topic_info)| TopicConnection {
-                id: idx as i32 + 1,
-                topic: topic_info.topic_metadata.name.clone(),
-                msgtype: topic_info.topic_metadata.type_.clone(),
-                msgcount: topic_info.message_count,
-                ext: ConnectionExt {
-                    serialization_format: topic_info.topic_metadata.serialization_format.clone(),
-                    offered_qos_profiles: topic_info.topic_metadata.offered_qos_profiles.clone(),
-                },
-            })
-            .collect::<Vec<_>>();
+    /// Opens the database files of the bag in `path` described by `metadata`.
+    fn with_metadata(path: &Path, metadata: Metadata) -> Result<Self> {
+        let compression = check_metadata(&metadata)?;
 
-        let paths: Vec<String> = metadata
+        let mut paths: Vec<PathBuf> = metadata
             .relative_file_paths
             .iter()
-            .map(|relative_path| path.join(relative_path).to_string_lossy().into_owned())
+            .map(|relative_path| path.join(relative_path))
             .collect();
-        let mut storage = Sqlite3Reader::new(paths);
 
-        println!("Opening storage");
+        // SQLite can only open plain files, so compressed files are decompressed up front.
+        // The last file of a bag whose recorder crashed may not have been compressed yet.
+        let decompressed = if compression == CompressionMode::File {
+            let dir = tempfile::tempdir()?;
+            for path in paths.iter_mut() {
+                if path.extension().is_some_and(|ext| ext == "zstd") {
+                    *path = decompress_file(path, dir.path())?;
+                }
+            }
+            Some(dir)
+        } else {
+            None
+        };
+
+        let mut storage = Sqlite3Reader::new(
+            paths
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect(),
+        );
+
+        debug!(
+            version = metadata.version,
+            files = paths.len(),
+            ?compression,
+            "opening storage"
+        );
         storage.open()?;
-        println!("Opening storage Done");
+        Self::with_storage(metadata, storage, compression, decompressed)
+    }
+
+    /// Creates the reader of the bag described by `metadata` with its opened `storage`.
+    fn with_storage(
+        metadata: Metadata,
+        storage: Sqlite3Reader,
+        compression: CompressionMode,
+        decompressed: Option<TempDir>,
+    ) -> Result<Self> {
+        // Initialize connections from the topics stored in the database, their ids are the
+        // ones referenced by messages. Fields missing from older database schemas are
+        // taken from the metadata.
+        let mut connections = storage.connections()?;
+        for connection in &mut connections {
+            let Some(topic_info) = metadata.topics_with_message_count.iter().find(|t| {
+                t.topic_metadata.name == connection.topic
+                    && t.topic_metadata.type_ == connection.msgtype
+            }) else {
+                continue;
+            };
+            let ext = &mut connection.ext;
+            if ext.offered_qos_profiles.is_empty() {
+                ext.offered_qos_profiles = topic_info.topic_metadata.offered_qos_profiles.clone();
+            }
+            if ext.type_description_hash.is_empty() {
+                ext.type_description_hash = topic_info.topic_metadata.type_description_hash.clone();
+            }
+        }
+
+        // counting the messages in the database reads all of them, so the counts of the
+        // metadata are used when it lists every connection
+        match metadata_counts(&metadata, &connections) {
+            Some(counts) => {
+                for (connection, count) in connections.iter_mut().zip(counts) {
+                    connection.msgcount = count;
+                }
+            }
+            None => {
+                debug!("counting messages in the database");
+                let counts = storage.message_counts()?;
+                for connection in &mut connections {
+                    connection.msgcount = counts.get(&(connection.id as i64)).copied().unwrap_or(0);
+                }
+            }
+        }
+
+        let message_connections = connections
+            .iter()
+            .map(|c| (c.id as i64, Arc::new(c.clone())))
+            .collect();
 
         Ok(Self {
             metadata,
             connections,
+            message_connections,
             storage,
+            compression,
+            decompressed: decompressed.map(Arc::new),
         })
     }
 
+    /// Recovers the metadata of a bag without metadata.yaml, e.g. when the recorder crashed.
+    ///
+    /// The metadata stored in the database is used if it lists every `.db3` file of the
+    /// bag, otherwise it is reconstructed from the `topics` and `messages` tables.
+    fn recover_metadata(path: &Path) -> Result<Metadata> {
+        let (files, storage) = open_db3_files(path)?;
+        match storage.stored_metadata()? {
+            Some(metadata) if metadata.relative_file_paths == files => {
+                debug!("using the metadata stored in the database");
+                Ok(metadata)
+            }
+            _ => {
+                debug!("reconstructing the metadata from the database");
+                storage.reconstruct_metadata()
+            }
+        }
+    }
+
     pub fn open(&mut self) -> Result<()> {
         self.storage.open()?;
 
         Ok(())
     }
 
-    pub fn handle_messages(
+    /// Returns another reader of the same bag with its own SQLite connections, so that
+    /// both can be read at the same time, e.g. from different threads.
+    ///
+    /// The metadata and connections are copied instead of read again, the database files
+    /// are opened again, or copied for a reader created by [`Reader::from_bytes`].
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(Self {
+            metadata: self.metadata.clone(),
+            connections: self.connections.clone(),
+            message_connections: self.message_connections.clone(),
+            storage: self.storage.try_clone()?,
+            compression: self.compression,
+            decompressed: self.decompressed.clone(),
+        })
+    }
+
+    /// Runs `handle_func` on every message within `[start, stop)`, in timestamp order.
+    ///
+    /// Reading stops early when the handler returns [`HandlerAction::Stop`]. An error of
+    /// the handler or of the storage ends the read and is returned.
+    pub fn handle_messages<R: Into<HandlerAction>>(
         &mut self,
-        handle_func: impl Fn((i64, i64, Vec<u8>)) -> Result<()>,
-        start: Option<i64>,
-        stop: Option<i64>,
+        mut handle_func: impl FnMut(Message) -> Result<R>,
+        start: Option<Time>,
+        stop: Option<Time>,
     ) -> Result<()> {
-        let statement = self
-            .storage
-            .messages_statement(&self.connections, start, stop)?;
-        handle_messages(statement, handle_func)?;
+        let filter = ReadFilter {
+            start,
+            stop,
+            ..Default::default()
+        };
+        for message in self.messages(&filter)? {
+            if handle_func(message?)?.into() == HandlerAction::Stop {
+                break;
+            }
+        }
         Ok(())
     }
 
-    pub fn duration(&self) -> i64 {
-        let nsecs = self.metadata.duration.nanoseconds;
-        if self.message_count() > 0 {
-            nsecs + 1
+    /// Returns an iterator over the messages selected by `filter`, in timestamp order or
+    /// latest first with [`ReadFilter::reverse`].
+    ///
+    /// The iterator borrows the reader and streams rows from storage as it is advanced.
+    pub fn messages(&self, filter: &ReadFilter) -> Result<Messages<'_>> {
+        let (connections, files) = self.select(filter);
+        let inner = self.storage.messages(
+            &files,
+            &connections,
+            filter.start.map(Time::as_nanos),
+            filter.stop.map(Time::as_nanos),
+            filter.direction(),
+        )?;
+        let decompress = self.compression == CompressionMode::Message && !filter.raw;
+        Ok(Messages {
+            reader: self,
+            inner,
+            decompress,
+        })
+    }
+
+    /// Runs `handle_func` on the messages selected by `filter`, in the order
+    /// [`Reader::messages`] returns them.
+    ///
+    /// Unlike the iterator, the data is borrowed from storage, so no allocation is made per
+    /// message. The data of message compressed bags is decompressed into one buffer that
+    /// is reused for all messages. Reading stops early when the handler returns
+    /// [`HandlerAction::Stop`].
+    ///
+    /// ```no_run
+    /// # use rosbag2_rs::{ReadFilter, Reader};
+    /// # fn main() -> rosbag2_rs::Result<()> {
+    /// let reader = Reader::new("path/to/bag")?;
+    /// let mut bytes = 0;
+    /// reader.for_each_message(&ReadFilter::default(), |message| {
+    ///     bytes += message.data.len();
+    ///     Ok(())
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn for_each_message<R: Into<HandlerAction>>(
+        &self,
+        filter: &ReadFilter,
+        mut handle_func: impl FnMut(MessageRef<'_>) -> Result<R>,
+    ) -> Result<()> {
+        let _span = debug_span!("for_each_message").entered();
+        let (connections, files) = self.select(filter);
+        let decompress = self.compression == CompressionMode::Message && !filter.raw;
+        let mut buffer = vec![];
+        self.storage.for_each_message(
+            &files,
+            &connections,
+            filter.start.map(Time::as_nanos),
+            filter.stop.map(Time::as_nanos),
+            filter.direction(),
+            |(id, timestamp, data)| {
+                let data = if decompress {
+                    buffer.clear();
+                    decompress_message_into(data, &mut buffer)?;
+                    &buffer
+                } else {
+                    data
+                };
+                handle_func(MessageRef {
+                    connection: self.message_connection(id)?,
+                    timestamp: Time(timestamp),
+                    publish_timestamp: None,
+                    data,
+                })
+            },
+        )
+    }
+
+    /// Runs `handle_func` on the [`MessageInfo`] of the messages selected by `filter`, in
+    /// the order [`Reader::messages`] returns them, without reading their data.
+    ///
+    /// The data of selected messages can be loaded afterwards with
+    /// [`Reader::load_payload`] or streamed with [`Reader::open_payload`].
+    ///
+    /// ```no_run
+    /// # use rosbag2_rs::{ReadFilter, Reader};
+    /// # fn main() -> rosbag2_rs::Result<()> {
+    /// let reader = Reader::new("path/to/bag")?;
+    /// let mut largest = None;
+    /// reader.scan_messages(&ReadFilter::default(), |info| {
+    ///     if largest.map_or(true, |(size, _)| info.size > size) {
+    ///         largest = Some((info.size, info.id));
+    ///     }
+    ///     Ok(())
+    /// })?;
+    /// if let Some((_, id)) = largest {
+    ///     let data = reader.load_payload(id)?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn scan_messages<R: Into<HandlerAction>>(
+        &self,
+        filter: &ReadFilter,
+        mut handle_func: impl FnMut(MessageInfo) -> Result<R>,
+    ) -> Result<()> {
+        let _span = debug_span!("scan_messages").entered();
+        let (connections, files) = self.select(filter);
+        self.storage.scan_messages(
+            &files,
+            &connections,
+            filter.start.map(Time::as_nanos),
+            filter.stop.map(Time::as_nanos),
+            filter.direction(),
+            |info| {
+                handle_func(MessageInfo {
+                    id: info.id,
+                    connection: Arc::clone(self.message_connection(info.topic_id)?),
+                    timestamp: Time(info.timestamp),
+                    size: info.size,
+                })
+            },
+        )
+    }
+
+    /// Loads the data of the message with the given id, decompressed for message
+    /// compressed bags.
+    pub fn load_payload(&self, id: MessageId) -> Result<Vec<u8>> {
+        let mut blob = self.storage.open_payload(id)?;
+        let mut data = Vec::with_capacity(blob.len());
+        blob.read_to_end(&mut data)?;
+        if self.compression == CompressionMode::Message {
+            decompress_message(&data)
         } else {
-            0
+            Ok(data)
         }
     }
 
-    pub fn start_time(&self) -> i64 {
-        let nsecs = self.metadata.starting_time.nanoseconds_since_epoch;
-        if self.message_count() > 0 {
-            nsecs
+    /// Opens the data of the message with the given id for reading it incrementally,
+    /// decompressed for message compressed bags.
+    pub fn open_payload(&self, id: MessageId) -> Result<Box<dyn io::Read + '_>> {
+        let blob = self.storage.open_payload(id)?;
+        if self.compression == CompressionMode::Message {
+            Ok(Box::new(zstd::Decoder::new(blob)?))
         } else {
-            i64::MAX
+            Ok(Box::new(blob))
+        }
+    }
+
+    /// Computes message counts, sizes, rates and gaps of the messages selected by
+    /// `filter`, per topic and for the whole bag. See [`BagStatistics`].
+    ///
+    /// Each database file is aggregated by a single SQL query that reads the sizes of the
+    /// messages but not their data. [`ReadFilter::raw`] and [`ReadFilter::reverse`] are
+    /// ignored.
+    pub fn statistics(&self, filter: &ReadFilter) -> Result<BagStatistics> {
+        let _span = debug_span!("statistics").entered();
+        let (connections, files) = self.select(filter);
+        let mut aggregates = vec![];
+        for index in files {
+            aggregates.extend(self.storage.file_statistics(
+                index,
+                &connections,
+                filter.start.map(Time::as_nanos),
+                filter.stop.map(Time::as_nanos),
+            )?);
         }
+        Ok(bag_statistics(&connections, aggregates))
     }
 
-    pub fn end_time(&self) -> i64 {
-        self.start_time() + self.duration()
+    /// Follows the bag at `path` while it is being recorded, returning the messages
+    /// selected by `filter` as they are written. See [`Follow`].
+    ///
+    /// No metadata.yaml is needed, and the bag directory does not even have to exist yet.
+    ///
+    /// ```no_run
+    /// # use rosbag2_rs::{FollowOptions, ReadFilter, Reader};
+    /// # use std::time::Duration;
+    /// # fn main() -> rosbag2_rs::Result<()> {
+    /// let options = FollowOptions {
+    ///     timeout: Some(Duration::from_secs(10)),
+    ///     ..Default::default()
+    /// };
+    /// for message in Reader::follow("path/to/bag", ReadFilter::default(), options) {
+    ///     let message = message?;
+    ///     println!("{} at {:?}", message.connection.topic, message.timestamp);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn follow(path: impl AsRef<Path>, filter: ReadFilter, options: FollowOptions) -> Follow {
+        Follow::new(path.as_ref(), filter, options)
+    }
+
+    /// Returns a cursor over the messages selected by `filter`, positioned before the
+    /// first of them.
+    ///
+    /// Unlike [`Reader::messages`], the cursor can [`seek`](Cursor::seek) to a timestamp
+    /// and step backwards with [`prev`](Cursor::prev). [`ReadFilter::reverse`] is ignored.
+    ///
+    /// ```no_run
+    /// # use rosbag2_rs::{Duration, ReadFilter, Reader};
+    /// # fn main() -> rosbag2_rs::Result<()> {
+    /// let reader = Reader::new("path/to/bag")?;
+    /// let mut cursor = reader.cursor(&ReadFilter::default())?;
+    /// if let Some(start) = reader.start_time() {
+    ///     cursor.seek(start + Duration(1_000_000_000));
+    /// }
+    /// let after = cursor.next().transpose()?;
+    /// let before = cursor.prev().transpose()?;
+    /// assert_eq!(after, before);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn cursor(&self, filter: &ReadFilter) -> Result<Cursor<'_>> {
+        let (connections, files) = self.select(filter);
+        let files = files
+            .into_iter()
+            .map(|index| {
+                let query = self.storage.file_query(
+                    index,
+                    &connections,
+                    filter.start.map(Time::as_nanos),
+                    filter.stop.map(Time::as_nanos),
+                )?;
+                Ok((index, query))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Cursor {
+            reader: self,
+            files,
+            decompress: self.compression == CompressionMode::Message && !filter.raw,
+            key: None,
+        })
+    }
+
+    /// Connections and file indices to read for `filter`.
+    fn select(&self, filter: &ReadFilter) -> (Vec<TopicConnection>, Vec<usize>) {
+        let connections = self
+            .connections
+            .iter()
+            .filter(|c| filter.matches(c))
+            .cloned()
+            .collect::<Vec<_>>();
+        // storage reads every topic for an empty selection, so skip all files instead
+        let files = if connections.is_empty() {
+            vec![]
+        } else {
+            self.files_in_window(filter.start, filter.stop)
+        };
+        debug!(
+            ?filter,
+            connections = connections.len(),
+            files = files.len(),
+            "selected messages"
+        );
+        (connections, files)
+    }
+
+    /// Returns the connection handed out with messages of the connection id `id`.
+    fn message_connection(&self, id: i64) -> Result<&Arc<TopicConnection>> {
+        self.message_connections.get(&id).ok_or_else(|| {
+            Rosbag2Error::SchemaMismatch(format!("message of unknown connection {id}"))
+        })
+    }
+
+    /// Builds the message of a storage row, decompressing its data if `decompress` is set.
+    fn message(&self, row: (i64, i64, Vec<u8>), decompress: bool) -> Result<Message> {
+        let (id, timestamp, data) = row;
+        Ok(Message {
+            connection: Arc::clone(self.message_connection(id)?),
+            timestamp: Time(timestamp),
+            publish_timestamp: None,
+            data: if decompress {
+                decompress_message(&data)?
+            } else {
+                data
+            },
+        })
+    }
+
+    /// Indices of the files in `relative_file_paths` that may hold messages within
+    /// `[start, stop)`, judged by the per-file information of the metadata.
+    ///
+    /// Files without such information are always included.
+    fn files_in_window(&self, start: Option<Time>, stop: Option<Time>) -> Vec<usize> {
+        self.metadata
+            .relative_file_paths
+            .iter()
+            .enumerate()
+            .filter(|(_, relative_path)| {
+                let file_name = Path::new(relative_path).file_name();
+                let Some(info) = self
+                    .metadata
+                    .files
+                    .iter()
+                    .find(|info| Path::new(&info.path).file_name() == file_name)
+                else {
+                    return true;
+                };
+
+                let first = info.starting_time.nanoseconds_since_epoch;
+                // saturating, so that a bogus duration keeps the file instead of panicking
+                let (first, last) = (
+                    Time(first),
+                    Time(first.saturating_add(info.duration.nanoseconds)),
+                );
+                info.message_count > 0
+                    && !matches!(start, Some(start) if last < start)
+                    && !matches!(stop, Some(stop) if first >= stop)
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Returns the time from the first message to just after the last one, `None` for a
+    /// bag without messages or a duration in the metadata that overflows.
+    pub fn duration(&self) -> Option<Duration> {
+        if self.message_count() <= 0 {
+            return None;
+        }
+        Duration(self.metadata.duration.nanoseconds).checked_add(Duration(1))
+    }
+
+    /// Returns the timestamp of the first message, `None` for a bag without messages.
+    pub fn start_time(&self) -> Option<Time> {
+        (self.message_count() > 0)
+            .then_some(Time(self.metadata.starting_time.nanoseconds_since_epoch))
+    }
+
+    /// Returns the time just after the last message, so that messages are within
+    /// `start_time..end_time`. `None` for a bag without messages or times in the metadata
+    /// that overflow.
+    pub fn end_time(&self) -> Option<Time> {
+        self.start_time()?.checked_add(self.duration()?)
     }
 
     pub fn message_count(&self) -> i32 {
@@ -156,29 +699,252 @@ impl Reader {
         self.metadata.compression_format.clone()
     }
 
+    /// Returns `"file"` or `"message"` for compressed bags, `None` otherwise.
     pub fn compression_mode(&self) -> Option<String> {
-        let mode = self.metadata.compression_mode.to_lowercase();
-        if mode != "none" {
-            Some(mode)
-        } else {
-            None
+        match self.compression {
+            CompressionMode::None => None,
+            CompressionMode::File => Some("file".to_string()),
+            CompressionMode::Message => Some("message".to_string()),
         }
     }
 
+    /// Returns the topics of the bag with all their connections, in the order of
+    /// [`Reader::connections`].
+    ///
+    /// A topic has several connections when it was recorded with different QoS profiles
+    /// or bags with different message types were merged. The message counts are summed,
+    /// and a conflict of message types is reported by a `None` [`TopicInfo::msgtype`].
     pub fn topics(&self) -> HashMap<String, TopicInfo> {
-        // Assuming TopicInfo is already defined
-        self.connections
-            .iter()
-            .map(|conn| {
-                (
+        let mut topics = HashMap::<String, TopicInfo>::new();
+        for conn in &self.connections {
+            let Some(info) = topics.get_mut(&conn.topic) else {
+                topics.insert(
                     conn.topic.clone(),
-                    TopicInfo::new(conn.msgtype.clone(), conn.msgcount, vec![conn.clone()]),
-                )
-            })
-            .collect()
+                    TopicInfo::new(
+                        Some(conn.msgtype.clone()),
+                        conn.msgcount,
+                        vec![conn.clone()],
+                    ),
+                );
+                continue;
+            };
+            if info
+                .msgtype
+                .as_ref()
+                .is_some_and(|msgtype| *msgtype != conn.msgtype)
+            {
+                warn!(topic = %conn.topic, "connections of topic have different message types");
+                info.msgtype = None;
+            }
+            info.msgcount += conn.msgcount;
+            info.connections.push(conn.clone());
+        }
+        topics
     }
 
     pub fn ros_distro(&self) -> String {
         self.metadata.ros_distro.clone()
     }
 }
+
+/// Iterator over the messages of a [`Reader`], created by [`Reader::messages`].
+pub struct Messages<'a> {
+    reader: &'a Reader,
+    inner: Sqlite3Messages<'a>,
+    decompress: bool,
+}
+
+impl Iterator for Messages<'_> {
+    type Item = Result<Message>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = self.inner.next()?;
+        Some(row.and_then(|row| self.reader.message(row, self.decompress)))
+    }
+}
+
+/// Cursor over the messages selected by a [`ReadFilter`] with random access by time, see
+/// [`Reader::cursor`].
+///
+/// The cursor sits between two messages. [`next`](Iterator::next) returns the message
+/// after it and [`prev`](Cursor::prev) the message before it, and both move the cursor
+/// past the returned message, so `prev` after `next` returns the same message again.
+/// Messages are ordered like [`Reader::messages`] yields them.
+///
+/// Every step runs one indexed query per database file, which makes the cursor suited
+/// to jumping around in a bag; sequential reads are faster with [`Reader::messages`].
+pub struct Cursor<'a> {
+    reader: &'a Reader,
+    files: Vec<(usize, Sqlite3FileQuery<'a>)>,
+    decompress: bool,
+    /// Key `(timestamp, file index, messages.id)` the cursor sits right after. It does not
+    /// have to be the key of a message. `None` is before all messages.
+    key: Option<(i64, usize, i64)>,
+}
+
+impl Cursor<'_> {
+    /// Moves the cursor before the first message at or after `timestamp`.
+    pub fn seek(&mut self, timestamp: impl Into<Time>) {
+        self.key = Some((timestamp.into().as_nanos(), 0, i64::MIN));
+    }
+
+    /// Returns the timestamp of the cursor: that of the message returned last, or the
+    /// timestamp passed to [`Cursor::seek`]. `None` before the cursor was moved.
+    pub fn position(&self) -> Option<Time> {
+        self.key.map(|(timestamp, _, _)| Time(timestamp))
+    }
+
+    /// Returns the message before the cursor and moves the cursor before it, or `None`
+    /// at the start.
+    pub fn prev(&mut self) -> Option<Result<Message>> {
+        self.step(Direction::Backward).transpose()
+    }
+
+    fn step(&mut self, direction: Direction) -> Result<Option<Message>> {
+        let (timestamp, file, rowid) = self.key.unwrap_or((i64::MIN, 0, i64::MIN));
+
+        // Translate the key into a `(timestamp, messages.id)` keyset per file. Messages
+        // with equal timestamps are ordered by file, so the messages of other files with
+        // the timestamp of the key all lie on one side of it.
+        let mut found: Option<(usize, Sqlite3Row)> = None;
+        for (index, query) in &self.files {
+            let keyset = match (index.cmp(&file), direction) {
+                (Ordering::Less, _) => (timestamp, i64::MAX),
+                (Ordering::Equal, Direction::Forward) => (timestamp, rowid),
+                (Ordering::Equal, Direction::Backward) => (timestamp, rowid.saturating_add(1)),
+                (Ordering::Greater, _) => (timestamp, i64::MIN),
+            };
+            let Some(row) = query.fetch(keyset, direction, 1, usize::MAX)?.pop() else {
+                continue;
+            };
+
+            let better = match &found {
+                None => true,
+                Some((best_index, best)) => {
+                    let key = (row.1, index, row.3);
+                    let best = (best.1, best_index, best.3);
+                    match direction {
+                        Direction::Forward => key < best,
+                        Direction::Backward => key > best,
+                    }
+                }
+            };
+            if better {
+                found = Some((*index, row));
+            }
+        }
+
+        let Some((index, (id, timestamp, data, rowid))) = found else {
+            return Ok(None);
+        };
+        self.key = Some(match direction {
+            Direction::Forward => (timestamp, index, rowid),
+            Direction::Backward => (timestamp, index, rowid - 1),
+        });
+        let message = self
+            .reader
+            .message((id, timestamp, data), self.decompress)?;
+        Ok(Some(message))
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = Result<Message>;
+
+    /// Returns the message after the cursor and moves the cursor past it, or `None` at
+    /// the end.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.step(Direction::Forward).transpose()
+    }
+}
+
+/// Checks that the bag described by `metadata` can be read and returns its compression
+/// mode.
+fn check_metadata(metadata: &Metadata) -> Result<CompressionMode> {
+    if metadata.version > LATEST_METADATA_VERSION {
+        return Err(Rosbag2Error::UnsupportedVersion(metadata.version));
+    }
+
+    let compression =
+        CompressionMode::from_metadata(&metadata.compression_mode, &metadata.compression_format)?;
+
+    if let Some(topic_info) = metadata
+        .topics_with_message_count
+        .iter()
+        .find(|t| t.topic_metadata.serialization_format != "cdr")
+    {
+        return Err(Rosbag2Error::UnsupportedSerialization(
+            topic_info.topic_metadata.serialization_format.clone(),
+        ));
+    }
+
+    if metadata.storage_identifier != "sqlite3" {
+        return Err(Rosbag2Error::UnsupportedStorage(
+            metadata.storage_identifier.clone(),
+        ));
+    }
+    Ok(compression)
+}
+
+/// Returns the message counts of `connections` listed in `metadata`, or `None` unless it
+/// lists each of them exactly once, e.g. for several connections of the same topic.
+fn metadata_counts(metadata: &Metadata, connections: &[TopicConnection]) -> Option<Vec<i32>> {
+    let topics = &metadata.topics_with_message_count;
+    if topics.len() != connections.len() {
+        return None;
+    }
+    let mut listed = HashSet::new();
+    connections
+        .iter()
+        .map(|connection| {
+            let mut found = topics.iter().enumerate().filter(|(_, t)| {
+                t.topic_metadata.name == connection.topic
+                    && t.topic_metadata.type_ == connection.msgtype
+            });
+            match (found.next(), found.next()) {
+                (Some((index, t)), None) if listed.insert(index) => Some(t.message_count),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Opens all `.db3` files in `dir` as one storage and returns their names along with it.
+pub(crate) fn open_db3_files(dir: &Path) -> Result<(Vec<String>, Sqlite3Reader)> {
+    let files = db3_files(dir)?;
+    if files.is_empty() {
+        return Err(Rosbag2Error::MissingMetadata(dir.to_path_buf()));
+    }
+
+    let mut storage = Sqlite3Reader::new(
+        files
+            .iter()
+            .map(|file| dir.join(file).to_string_lossy().into_owned())
+            .collect(),
+    );
+    storage.open()?;
+    Ok((files, storage))
+}
+
+/// Returns the names of the `.db3` files in `dir`, ordered by their split index
+/// (`<name>_<index>.db3`).
+pub(crate) fn db3_files(dir: &Path) -> Result<Vec<String>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "db3") {
+            if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+                files.push(name.to_string());
+            }
+        }
+    }
+
+    let split_index = |name: &str| {
+        name.trim_end_matches(".db3")
+            .rsplit('_')
+            .next()
+            .and_then(|index| index.parse::<u64>().ok())
+    };
+    files.sort_by(|a, b| (split_index(a), a).cmp(&(split_index(b), b)));
+    Ok(files)
+}
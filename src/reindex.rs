@@ -0,0 +1,55 @@
+//! Regenerating the metadata.yaml of a bag from its database files, like
+//! `ros2 bag reindex`.
+
+use crate::*;
+use std::fs::{self, File};
+use std::path::Path;
+use tracing::debug_span;
+
+/// Rebuilds the metadata.yaml of the bag at `path` from its `.db3` files and returns the
+/// new metadata.
+///
+/// Every file is scanned for its topics and the time range and count of its messages,
+/// so bags whose metadata.yaml is missing, stale or wrong can be opened again. The
+/// `custom_data` and the compression of an existing, readable metadata.yaml, or else of
+/// the metadata stored in the database, are kept, as the database files do not record
+/// them. Bags compressed in file mode are not supported.
+pub fn reindex(path: impl AsRef<Path>) -> Result<Metadata> {
+    let path = path.as_ref();
+    let _span = debug_span!("reindex", path = %path.display()).entered();
+    let (_, storage) = open_db3_files(path)?;
+    let mut metadata = storage.reconstruct_metadata()?;
+
+    let metapath = path.join("metadata.yaml");
+    let previous = fs::read_to_string(&metapath)
+        .ok()
+        .and_then(|contents| serde_yaml::from_str::<BagFileInfo>(&contents).ok())
+        .map(|info| info.rosbag2_bagfile_information)
+        .or_else(|| storage.stored_metadata().ok().flatten());
+    if let Some(previous) = previous {
+        let compression = CompressionMode::from_metadata(
+            &previous.compression_mode,
+            &previous.compression_format,
+        )?;
+        if compression == CompressionMode::File {
+            return Err(Rosbag2Error::UnsupportedCompression(format!(
+                "{} (reindexing file compressed bags)",
+                previous.compression_mode
+            )));
+        }
+        // message compressed files are plain .db3 files holding compressed data
+        metadata.compression_mode = previous.compression_mode;
+        metadata.compression_format = previous.compression_format;
+        metadata.custom_data = previous.custom_data;
+        // custom data is only written from version 6 on
+        if !metadata.custom_data.is_empty() {
+            metadata.version = metadata.version.max(6);
+        }
+    }
+
+    let info = BagFileInfo {
+        rosbag2_bagfile_information: metadata,
+    };
+    serde_yaml::to_writer(File::create(metapath)?, &info)?;
+    Ok(info.rosbag2_bagfile_information)
+}
@@ -1,12 +1,46 @@
 use crate::*;
-use anyhow::Result;
-use rusqlite::{Connection, Statement};
+use fallible_streaming_iterator::FallibleStreamingIterator;
+use rusqlite::blob::Blob;
+use rusqlite::ffi;
+use rusqlite::serialize::OwnedData;
+use rusqlite::types::Value;
+use rusqlite::types::ValueRef;
+use rusqlite::{
+    params_from_iter, CachedStatement, Connection, DatabaseName, OptionalExtension, Row, Rows,
+    Statement,
+};
+use std::cell::OnceCell;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
 use std::path::Path;
+use std::ptr::NonNull;
+use tracing::{debug, trace};
+
+/// Maximum number of rows fetched by a single page query of [`Sqlite3Messages`].
+const PAGE_ROWS: i64 = 256;
+
+/// A page is cut short once it buffers this many payload bytes, so that bags with
+/// large messages (images, point clouds) do not keep hundreds of them in memory.
+const PAGE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Column of the message data in message queries.
+const DATA_COLUMN: &str = "messages.data";
+
+/// Selects the size of the message data instead, which SQLite computes without reading
+/// the data itself.
+const SIZE_COLUMN: &str = "length(messages.data)";
 
 pub struct Sqlite3Reader {
     paths: Vec<String>, // Assuming paths are stored as strings
     dbconns: Vec<Connection>,
     schema: i32,
+    /// Maps the topic ids of each file to the ids of the connections, see
+    /// [`Sqlite3Reader::connections`].
+    topic_ids: OnceCell<Vec<HashMap<i64, i64>>>,
+    /// Number of messages of each connection, see [`Sqlite3Reader::message_counts`].
+    message_counts: OnceCell<HashMap<i64, i32>>,
+    /// Whether the databases were deserialized by [`Sqlite3Reader::open_bytes`].
+    in_memory: bool,
     // msgtypes: Vec<String>,
     // connections: Vec<TopicConnection>,
 }
@@ -17,36 +51,69 @@ impl Sqlite3Reader {
             paths,
             dbconns: Vec::new(),
             schema: 0,
+            topic_ids: OnceCell::new(),
+            message_counts: OnceCell::new(),
+            in_memory: false,
         }
     }
 
     pub fn open(&mut self) -> Result<()> {
         for path_str in &self.paths {
             let path = Path::new(path_str);
-            println!("opening db using path {path:?}");
+            debug!(path = path_str, "opening database");
             let conn = Connection::open_with_flags(
                 path,
                 rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY | rusqlite::OpenFlags::SQLITE_OPEN_URI,
             )?;
+            check_tables(&conn, path_str)?;
+            self.dbconns.push(conn);
+        }
+        self.read_schema()
+    }
 
-            {
-                let mut stmt = conn.prepare(
-                    "SELECT count(*) FROM sqlite_master WHERE type='table' AND name IN ('messages', 'topics')",
-                )?;
-
-                let table_count: i32 = stmt.query_row([], |row| row.get(0))?;
+    /// Opens the database files from their contents instead of reading them from the
+    /// paths this reader was created with, which then only name the files.
+    ///
+    /// Every file is deserialized into a read-only in-memory database.
+    pub fn open_bytes(&mut self, files: Vec<Vec<u8>>) -> Result<()> {
+        for (name, data) in self.paths.iter().zip(files) {
+            debug!(name, size = data.len(), "opening database from memory");
+            let mut conn = Connection::open_in_memory()?;
+            conn.deserialize(DatabaseName::Main, sqlite_owned_data(&data)?, true)?;
+            check_tables(&conn, name)?;
+            self.dbconns.push(conn);
+        }
+        self.in_memory = true;
+        self.read_schema()
+    }
 
-                if table_count != 2 {
-                    return Err(anyhow::anyhow!(
-                        "Cannot open database {} or database missing tables.",
-                        path_str
-                    ));
-                }
+    /// Opens another reader of the same databases, with connections of its own.
+    ///
+    /// Files are opened again from their paths, in-memory databases are copied.
+    pub fn try_clone(&self) -> Result<Self> {
+        let mut reader = Sqlite3Reader {
+            paths: self.paths.clone(),
+            dbconns: Vec::new(),
+            schema: self.schema,
+            topic_ids: self.topic_ids.clone(),
+            message_counts: self.message_counts.clone(),
+            in_memory: self.in_memory,
+        };
+        if self.in_memory {
+            for (name, conn) in self.paths.iter().zip(&self.dbconns) {
+                let data = conn.serialize(DatabaseName::Main)?;
+                let mut copy = Connection::open_in_memory()?;
+                copy.deserialize(DatabaseName::Main, sqlite_owned_data(&data)?, true)?;
+                check_tables(&copy, name)?;
+                reader.dbconns.push(copy);
             }
-
-            self.dbconns.push(conn);
+        } else if !self.dbconns.is_empty() {
+            reader.open()?;
         }
+        Ok(reader)
+    }
 
+    fn read_schema(&mut self) -> Result<()> {
         // Check the schema version and initialize `self.schema` and `self.msgtypes`
         if let Some(conn) = self.dbconns.last() {
             let mut stmt = conn.prepare("PRAGMA table_info(schema)")?;
@@ -83,94 +150,1083 @@ impl Sqlite3Reader {
         self.dbconns.clear();
     }
 
+    /// Returns the bag metadata stored in the `metadata` table of the last file, if any.
+    ///
+    /// rosbag2 writes this table when it closes a file, so it is missing or outdated for
+    /// files that were still being recorded.
+    pub fn stored_metadata(&self) -> Result<Option<Metadata>> {
+        let conn = self.last_connection()?;
+        if !table_exists(conn, "metadata")? {
+            return Ok(None);
+        }
+
+        let text: Option<String> = conn
+            .query_row(
+                "SELECT metadata FROM metadata ORDER BY id DESC LIMIT 1",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        text.map(|text| {
+            // rosbag2 stores the same document as metadata.yaml, accept it without the root key too
+            serde_yaml::from_str::<BagFileInfo>(&text)
+                .map(|info| info.rosbag2_bagfile_information)
+                .or_else(|_| serde_yaml::from_str::<Metadata>(&text))
+                .map_err(Rosbag2Error::from)
+        })
+        .transpose()
+    }
+
+    /// Rebuilds the bag metadata from the `topics` and `messages` tables of all files.
+    pub fn reconstruct_metadata(&self) -> Result<Metadata> {
+        let conn = self.last_connection()?;
+
+        let counts = self.message_counts()?;
+        let topics_with_message_count = self
+            .connections()?
+            .into_iter()
+            .map(|topic| TopicWithMessageCount {
+                message_count: counts.get(&(topic.id as i64)).copied().unwrap_or(0),
+                topic_metadata: TopicMetadata {
+                    name: topic.topic,
+                    type_: topic.msgtype,
+                    serialization_format: topic.ext.serialization_format,
+                    offered_qos_profiles: topic.ext.offered_qos_profiles,
+                    type_description_hash: topic.ext.type_description_hash,
+                },
+            })
+            .collect::<Vec<_>>();
+        let files = (0..self.dbconns.len())
+            .map(|index| self.file_information(index))
+            .collect::<Result<Vec<_>>>()?;
+
+        let non_empty = files.iter().filter(|f| f.message_count > 0);
+        let start = non_empty
+            .clone()
+            .map(|f| f.starting_time.nanoseconds_since_epoch)
+            .min()
+            .unwrap_or(0);
+        let end = non_empty
+            .map(|f| {
+                f.starting_time
+                    .nanoseconds_since_epoch
+                    .saturating_add(f.duration.nanoseconds)
+            })
+            .max()
+            .unwrap_or(0);
+
+        let ros_distro = if table_exists(conn, "schema")? {
+            conn.query_row("SELECT ros_distro FROM schema", [], |row| row.get(0))
+                .optional()?
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+
+        // the oldest metadata version holding the fields found, see the `metadata` module
+        let has_hashes = topics_with_message_count
+            .iter()
+            .any(|t| !t.topic_metadata.type_description_hash.is_empty());
+        let version = if !ros_distro.is_empty() {
+            8
+        } else if has_hashes {
+            7
+        } else {
+            5
+        };
+
+        Ok(Metadata {
+            version,
+            storage_identifier: "sqlite3".to_string(),
+            relative_file_paths: files.iter().map(|f| f.path.clone()).collect(),
+            starting_time: StartingTime {
+                nanoseconds_since_epoch: start,
+            },
+            duration: BagDuration {
+                nanoseconds: end.saturating_sub(start),
+            },
+            message_count: files.iter().map(|f| f.message_count).sum(),
+            compression_format: String::new(),
+            compression_mode: String::new(),
+            topics_with_message_count,
+            files,
+            custom_data: HashMap::new(),
+            ros_distro,
+        })
+    }
+
+    /// Returns the connections of all files, as stored in their `topics` tables, without
+    /// message counts, see [`Sqlite3Reader::message_counts`].
+    ///
+    /// Every row of a file is a connection of its own, also when a topic was recorded
+    /// with several QoS profiles. Rows of different files with the same topic name and
+    /// type are merged into one connection, rows with the same QoS profiles first. A connection keeps the id of the first file it appears
+    /// in; should a later file use that id for another topic, the connection gets the next
+    /// free id instead.
+    pub fn connections(&self) -> Result<Vec<TopicConnection>> {
+        let mut connections: Vec<TopicConnection> = vec![];
+        let mut topic_ids = vec![];
+        for index in 0..self.dbconns.len() {
+            let mut ids = HashMap::new();
+            for mut topic in self.topics(index)? {
+                let file_id = topic.id as i64;
+                // connections already holding a row of this file are not merged again
+                let mergeable = |c: &TopicConnection| {
+                    c.topic == topic.topic
+                        && c.msgtype == topic.msgtype
+                        && !ids.values().any(|&id| id == c.id as i64)
+                };
+                let existing = connections
+                    .iter()
+                    .position(|c| {
+                        mergeable(c) && c.ext.offered_qos_profiles == topic.ext.offered_qos_profiles
+                    })
+                    .or_else(|| connections.iter().position(mergeable));
+                if let Some(existing) = existing {
+                    ids.insert(file_id, connections[existing].id as i64);
+                    continue;
+                }
+                if connections.iter().any(|c| c.id == topic.id) {
+                    topic.id = connections.iter().map(|c| c.id).max().unwrap_or(0) + 1;
+                }
+                ids.insert(file_id, topic.id as i64);
+                connections.push(topic);
+            }
+            topic_ids.push(ids);
+        }
+        // the files do not change while they are read
+        let _ = self.topic_ids.set(topic_ids);
+        Ok(connections)
+    }
+
+    /// Returns the maps from the topic ids of each file to the ids of the connections.
+    fn topic_ids(&self) -> Result<&[HashMap<i64, i64>]> {
+        if self.topic_ids.get().is_none() {
+            self.connections()?;
+        }
+        Ok(self.topic_ids.get().map(Vec::as_slice).unwrap_or_default())
+    }
+
+    /// Returns the rows of the `topics` table of the file at `index`, with a message count
+    /// of zero.
+    pub fn topics(&self, index: usize) -> Result<Vec<TopicConnection>> {
+        read_topics(self.connection(index)?, 0)
+    }
+
+    /// Returns the number of messages of each connection, by connection id.
+    ///
+    /// Counting them reads the `messages` table of every file, so they are only counted
+    /// on the first call.
+    pub fn message_counts(&self) -> Result<&HashMap<i64, i32>> {
+        if let Some(counts) = self.message_counts.get() {
+            return Ok(counts);
+        }
+        let topic_ids = self.topic_ids()?;
+        let mut counts = HashMap::new();
+        for (index, ids) in topic_ids.iter().enumerate() {
+            let mut stmt = self
+                .connection(index)?
+                .prepare("SELECT topic_id, count(*) FROM messages GROUP BY topic_id")?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                // messages of topic ids missing from the topics table are never read
+                if let Some(id) = ids.get(&row.get(0)?) {
+                    *counts.entry(*id).or_insert(0) += row.get::<_, i32>(1)?;
+                }
+            }
+        }
+        Ok(self.message_counts.get_or_init(|| counts))
+    }
+
+    /// Computes the `files` entry of the metadata for the file at `index` from its messages.
+    pub fn file_information(&self, index: usize) -> Result<FileInformation> {
+        let conn = self.connection(index)?;
+
+        let (start, end, count): (Option<i64>, Option<i64>, i32) = conn.query_row(
+            "SELECT min(timestamp), max(timestamp), count(*) FROM messages",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+
+        let path = Path::new(&self.paths[index])
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.paths[index].clone());
+
+        Ok(FileInformation {
+            path,
+            starting_time: StartingTime {
+                nanoseconds_since_epoch: start.unwrap_or(0),
+            },
+            duration: BagDuration {
+                nanoseconds: end
+                    .zip(start)
+                    .map_or(0, |(end, start)| end.saturating_sub(start)),
+            },
+            message_count: count,
+        })
+    }
+
     // pub fn get_statuement(&self) -> Statement {}
 
+    /// Prepares the ordered messages query of a single-file bag for [`handle_messages`].
+    ///
+    /// Topic names and time bounds are bound to the returned statement, so it has to be
+    /// run with [`Statement::raw_query`].
     pub fn messages_statement(
         &self,
         connections: &[TopicConnection],
         start: Option<i64>,
         stop: Option<i64>,
-    ) -> Result<Statement> {
-        if self.dbconns.is_empty() {
-            return Err(anyhow::anyhow!("Rosbag has not been opened."));
-        }
+    ) -> Result<Statement<'_>> {
+        let conn = self.single_connection()?;
+
+        let topics = connections
+            .iter()
+            .map(|c| Value::Integer(c.id as i64))
+            .collect();
+        let (conditions, args) = messages_conditions("topics.id", topics, start, stop, 1);
 
         let mut query = String::from(
             "SELECT topics.id, messages.timestamp, messages.data FROM messages JOIN topics ON messages.topic_id=topics.id",
         );
-        let mut args: Vec<String> = vec![];
-        let mut clause = "WHERE";
-
-        if !connections.is_empty() {
-            let topics: Vec<String> = connections.iter().map(|c| c.topic.clone()).collect();
-            let formated_topics = topics
-                .iter()
-                .map(|t| format!("'{}'", t))
-                .collect::<Vec<_>>()
-                .join(", ");
-            query.push_str(&format!(" {clause} topics.name IN ({formated_topics})"));
-            clause = "AND";
-        }
-
-        if let Some(start_ns) = start {
-            // query.push_str(&format!(" {clause} messages.timestamp >= ?", clause));
-            query.push_str(&format!(" {clause} messages.timestamp >= {start_ns}"));
-            args.push(start_ns.to_string());
-            clause = "AND";
-        }
-
-        if let Some(stop_ns) = stop {
-            // query.push_str(&format!(" {} messages.timestamp < ?", clause));
-            query.push_str(&format!(" {clause} messages.timestamp < {stop_ns}"));
-            args.push(stop_ns.to_string());
-        }
-
-        query.push_str(" ORDER BY messages.timestamp");
-
-        if self.dbconns.len() > 1 {
-            panic!("not support multiple db3 files");
-        }
-
-        return if let Some(conn) = self.dbconns.last() {
-            println!("query string is {query}");
-            let stmt = conn.prepare(&query)?;
-            // let rows = stmt.query([])?; parse_row
-            // let messages = stmt.query_map([], parse_row)?;
-            // let messages = stmt.query_map([], |row| {
-            //     let id: i32 = row.get(0)?;
-            //     let timestamp: i64 = row.get(1)?;
-            //     let data: Vec<u8> = row.get(2)?;
-            //     Ok((id, timestamp, data))
-            // })?;
-            // let
-            Ok(stmt)
-            // let t = messages.into_iter();
-            // Ok(messages)
-            // Err(anyhow::anyhow!("Cannot open database."))
+        if !conditions.is_empty() {
+            query.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        }
+        query.push_str(" ORDER BY messages.timestamp, messages.id");
+
+        trace!(query, ?args, "preparing messages query");
+        let mut stmt = conn.prepare(&query)?;
+        for (index, arg) in args.iter().enumerate() {
+            stmt.raw_bind_parameter(index + 1, arg)?;
+        }
+        Ok(stmt)
+    }
+
+    /// Returns a streaming iterator over the messages of the given connections within
+    /// `[start, stop)` stored in the database files at `files` (indices into the paths
+    /// this reader was created with).
+    ///
+    /// Messages of all files are merged into one stream ordered by timestamp, ascending or
+    /// descending depending on `direction`. Rows are fetched page by page, so the iterator
+    /// only borrows the reader and no statement has to be kept alive between calls to
+    /// `next`.
+    pub fn messages(
+        &self,
+        files: &[usize],
+        connections: &[TopicConnection],
+        start: Option<i64>,
+        stop: Option<i64>,
+        direction: Direction,
+    ) -> Result<Sqlite3Messages<'_>> {
+        if self.dbconns.is_empty() {
+            return Err(Rosbag2Error::NotOpen);
+        }
+
+        let sources = files
+            .iter()
+            .map(|&index| self.file_messages(index, connections, start, stop, direction))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Sqlite3Messages::new(sources, direction))
+    }
+
+    /// Runs `handle_func` on `(topic id, timestamp, data)` of the messages that
+    /// [`Sqlite3Reader::messages`] returns for the same arguments, in the same order.
+    ///
+    /// `data` is borrowed from the SQLite row instead of copied into a `Vec`, which makes
+    /// this the cheapest way to inspect or forward the data of large messages. Reading
+    /// stops early when the handler returns [`HandlerAction::Stop`].
+    pub fn for_each_message<F, R>(
+        &self,
+        files: &[usize],
+        connections: &[TopicConnection],
+        start: Option<i64>,
+        stop: Option<i64>,
+        direction: Direction,
+        mut handle_func: F,
+    ) -> Result<()>
+    where
+        F: FnMut((i64, i64, &[u8])) -> Result<R>,
+        R: Into<HandlerAction>,
+    {
+        let queries = self.file_queries(files, connections, start, stop)?;
+        merge_rows(queries, direction, DATA_COLUMN, |_, query, row| {
+            let data = match row.get_ref(2)? {
+                ValueRef::Blob(data) => data,
+                value => {
+                    return Err(rusqlite::Error::InvalidColumnType(
+                        2,
+                        "data".to_string(),
+                        value.data_type(),
+                    )
+                    .into())
+                }
+            };
+            Ok(handle_func((query.topic_id(row)?, row.get(1)?, data))?.into())
+        })
+    }
+
+    /// Runs `handle_func` on the [`Sqlite3MessageInfo`] of the messages that
+    /// [`Sqlite3Reader::messages`] returns for the same arguments, in the same order.
+    ///
+    /// The message data is not read, only its size. It can be loaded afterwards with
+    /// [`Sqlite3Reader::open_payload`]. Reading stops early when the handler returns
+    /// [`HandlerAction::Stop`].
+    pub fn scan_messages<F, R>(
+        &self,
+        files: &[usize],
+        connections: &[TopicConnection],
+        start: Option<i64>,
+        stop: Option<i64>,
+        direction: Direction,
+        mut handle_func: F,
+    ) -> Result<()>
+    where
+        F: FnMut(Sqlite3MessageInfo) -> Result<R>,
+        R: Into<HandlerAction>,
+    {
+        let queries = self.file_queries(files, connections, start, stop)?;
+        merge_rows(queries, direction, SIZE_COLUMN, |file, query, row| {
+            let info = Sqlite3MessageInfo {
+                id: MessageId {
+                    file,
+                    row: row.get(3)?,
+                },
+                topic_id: query.topic_id(row)?,
+                timestamp: row.get(1)?,
+                size: row.get(2)?,
+            };
+            Ok(handle_func(info)?.into())
+        })
+    }
+
+    /// Opens the data of a message for incremental reading, without loading it into
+    /// memory. The returned blob implements [`std::io::Read`] and [`std::io::Seek`].
+    pub fn open_payload(&self, id: MessageId) -> Result<Blob<'_>> {
+        let conn = self.connection(id.file)?;
+        Ok(conn.blob_open(DatabaseName::Main, "messages", "data", id.row, true)?)
+    }
+
+    /// Queries of the given files that may return rows, with their file index.
+    fn file_queries(
+        &self,
+        files: &[usize],
+        connections: &[TopicConnection],
+        start: Option<i64>,
+        stop: Option<i64>,
+    ) -> Result<Vec<(usize, Sqlite3FileQuery<'_>)>> {
+        if self.dbconns.is_empty() {
+            return Err(Rosbag2Error::NotOpen);
+        }
+        let mut queries = vec![];
+        for &index in files {
+            let query = self.file_query(index, connections, start, stop)?;
+            if !query.empty {
+                queries.push((index, query));
+            }
+        }
+        Ok(queries)
+    }
+
+    /// Prepares the query for the messages of `connections` within `[start, stop)` in the
+    /// file with the given index. Empty `connections` select all topics.
+    ///
+    /// Topic ids of the file are mapped to the ids of the connections, as returned by
+    /// [`Sqlite3Reader::connections`].
+    pub fn file_query(
+        &self,
+        index: usize,
+        connections: &[TopicConnection],
+        start: Option<i64>,
+        stop: Option<i64>,
+    ) -> Result<Sqlite3FileQuery<'_>> {
+        let conn = self.connection(index)?;
+
+        let topic_ids = self
+            .topic_ids()?
+            .get(index)
+            .into_iter()
+            .flatten()
+            .filter(|(_, &id)| {
+                connections.is_empty() || connections.iter().any(|c| c.id as i64 == id)
+            })
+            .map(|(&file_id, &id)| (file_id, id))
+            .collect::<HashMap<_, _>>();
+
+        // a file without any of the requested topics has nothing to read
+        let empty = !connections.is_empty() && topic_ids.is_empty();
+        let topics = if connections.is_empty() {
+            vec![]
         } else {
-            Err(anyhow::anyhow!("Cannot open database."))
+            topic_ids.keys().map(|&id| Value::Integer(id)).collect()
+        };
+        // ?1 to ?3 are the keyset and the row limit, see `Sqlite3FileQuery::statement`
+        let (conditions, args) = messages_conditions("messages.topic_id", topics, start, stop, 4);
+
+        Ok(Sqlite3FileQuery {
+            conn,
+            conditions,
+            args,
+            topic_ids,
+            empty,
+        })
+    }
+
+    /// Returns the messages of the given connections stored in the single database file
+    /// at `index`, ordered as given by `direction`.
+    ///
+    /// Topic ids of the file are translated to the ids of the connections returned by
+    /// [`Sqlite3Reader::connections`], because each file of a split bag carries its own
+    /// `topics` table. Only topics of the file that map to one of `connections` are read;
+    /// an empty slice reads all.
+    pub fn file_messages(
+        &self,
+        index: usize,
+        connections: &[TopicConnection],
+        start: Option<i64>,
+        stop: Option<i64>,
+        direction: Direction,
+    ) -> Result<Sqlite3FileMessages<'_>> {
+        let query = self.file_query(index, connections, start, stop)?;
+        Ok(Sqlite3FileMessages {
+            exhausted: query.empty,
+            query,
+            direction,
+            last: direction.first_keyset(),
+            page_rows: 1,
+            buffer: VecDeque::new(),
+        })
+    }
+
+    /// Aggregates the messages of `connections` within `[start, stop)` in the file with
+    /// the given index per topic name, in a single pass that reads sizes but no data.
+    ///
+    /// The connections of a topic are aggregated together, gaps are those between
+    /// consecutive messages of any of them. Topics without messages are left out.
+    pub fn file_statistics(
+        &self,
+        index: usize,
+        connections: &[TopicConnection],
+        start: Option<i64>,
+        stop: Option<i64>,
+    ) -> Result<Vec<Sqlite3TopicAggregate>> {
+        let query = self.file_query(index, connections, start, stop)?;
+        if query.empty {
+            return Ok(vec![]);
+        }
+        let topics = if connections.is_empty() {
+            vec![]
+        } else {
+            query
+                .topic_ids
+                .keys()
+                .map(|&id| Value::Integer(id))
+                .collect()
+        };
+        let (conditions, args) = messages_conditions("messages.topic_id", topics, start, stop, 1);
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+
+        // squares are summed as floats, squared gaps of a second overflow 64 bit integers
+        let sql = format!(
+            "SELECT topic, count(*), sum(size), min(size), max(size), sum(size * 1.0 * size), \
+             min(timestamp), max(timestamp), min(gap), max(gap), total(gap * 1.0 * gap) \
+             FROM (SELECT topics.name AS topic, messages.timestamp AS timestamp, \
+             {SIZE_COLUMN} AS size, messages.timestamp - lag(messages.timestamp) \
+             OVER (PARTITION BY topics.name ORDER BY messages.timestamp, messages.id) AS gap \
+             FROM messages JOIN topics ON topics.id = messages.topic_id {where_clause}) \
+             GROUP BY topic ORDER BY topic"
+        );
+        trace!(query = sql, ?args, "aggregating messages");
+        let mut stmt = query.conn.prepare(&sql)?;
+        let mut rows = stmt.query(params_from_iter(args))?;
+        let mut aggregates = vec![];
+        while let Some(row) = rows.next()? {
+            aggregates.push(Sqlite3TopicAggregate {
+                topic: row.get(0)?,
+                count: row.get(1)?,
+                bytes: row.get(2)?,
+                min_size: row.get(3)?,
+                max_size: row.get(4)?,
+                size_squares: row.get(5)?,
+                first: row.get(6)?,
+                last: row.get(7)?,
+                min_gap: row.get(8)?,
+                max_gap: row.get(9)?,
+                gap_squares: row.get(10)?,
+            });
+        }
+        Ok(aggregates)
+    }
+
+    fn connection(&self, index: usize) -> Result<&Connection> {
+        self.dbconns
+            .get(index)
+            .ok_or(Rosbag2Error::UnknownFile(index))
+    }
+
+    fn last_connection(&self) -> Result<&Connection> {
+        self.dbconns.last().ok_or(Rosbag2Error::NotOpen)
+    }
+
+    fn single_connection(&self) -> Result<&Connection> {
+        match self.dbconns.as_slice() {
+            [] => Err(Rosbag2Error::NotOpen),
+            [conn] => Ok(conn),
+            conns => Err(Rosbag2Error::MultipleFiles(conns.len())),
+        }
+    }
+}
+
+/// Fails unless the database `name` has the `messages` and `topics` tables.
+fn check_tables(conn: &Connection, name: &str) -> Result<()> {
+    let table_count: i32 = conn.query_row(
+        "SELECT count(*) FROM sqlite_master WHERE type='table' AND name IN ('messages', 'topics')",
+        [],
+        |row| row.get(0),
+    )?;
+    if table_count != 2 {
+        return Err(Rosbag2Error::SchemaMismatch(format!(
+            "database {name} is missing the messages or topics table"
+        )));
+    }
+    Ok(())
+}
+
+/// Copies a database file into memory allocated by SQLite, which takes ownership of the
+/// databases it deserializes.
+fn sqlite_owned_data(data: &[u8]) -> Result<OwnedData> {
+    // SAFETY: the allocation is checked for null and at least `data.len()` bytes long,
+    // so copying `data` into it stays in bounds
+    let ptr = unsafe { ffi::sqlite3_malloc64(data.len().max(1) as u64) }.cast::<u8>();
+    let ptr = NonNull::new(ptr).ok_or(rusqlite::Error::SqliteFailure(
+        ffi::Error::new(ffi::SQLITE_NOMEM),
+        None,
+    ))?;
+    unsafe {
+        std::ptr::copy_nonoverlapping(data.as_ptr(), ptr.as_ptr(), data.len());
+    }
+
+    // in-memory databases cannot use a write-ahead log, a file recorded in WAL mode is
+    // read like one in rollback journal mode, which it is equivalent to once checkpointed
+    if data.len() > 19 && data[18] == 2 && data[19] == 2 {
+        // SAFETY: both bytes are within the copied data
+        unsafe {
+            *ptr.as_ptr().add(18) = 1;
+            *ptr.as_ptr().add(19) = 1;
+        }
+    }
+    // SAFETY: the memory was allocated by `sqlite3_malloc64`
+    Ok(unsafe { OwnedData::from_raw_nonnull(ptr, data.len()) })
+}
+
+fn table_exists(conn: &Connection, table: &str) -> Result<bool> {
+    let count: i32 = conn.query_row(
+        "SELECT count(*) FROM sqlite_master WHERE type='table' AND name=?1",
+        [table],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        if row.get::<_, String>(1)? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Reads the topics with an id greater than `after` as connections with their ids in the
+/// file and a message count of zero.
+fn read_topics(conn: &Connection, after: i64) -> Result<Vec<TopicConnection>> {
+    // columns added by later schema versions
+    let qos = if has_column(conn, "topics", "offered_qos_profiles")? {
+        "offered_qos_profiles"
+    } else {
+        "''"
+    };
+    let hash = if has_column(conn, "topics", "type_description_hash")? {
+        "type_description_hash"
+    } else {
+        "''"
+    };
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, name, type, serialization_format, {qos}, {hash} FROM topics WHERE id > ?1 ORDER BY id"
+    ))?;
+    let topics = stmt
+        .query_map([after], |row| {
+            Ok(TopicConnection {
+                id: row.get(0)?,
+                topic: row.get(1)?,
+                msgtype: row.get(2)?,
+                msgcount: 0,
+                ext: ConnectionExt {
+                    serialization_format: row.get(3)?,
+                    offered_qos_profiles: row.get(4)?,
+                    type_description_hash: row.get(5)?,
+                },
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(topics)
+}
+
+/// Builds the conditions shared by the message queries: `topic_column` has to be one of
+/// `topics` (unless it is empty) and the timestamp has to lie within `[start, stop)`.
+///
+/// Values are bound as numbered parameters starting at `?{first}` and returned in order.
+fn messages_conditions(
+    topic_column: &str,
+    topics: Vec<Value>,
+    start: Option<i64>,
+    stop: Option<i64>,
+    first: usize,
+) -> (Vec<String>, Vec<Value>) {
+    let mut conditions = vec![];
+    let mut args = vec![];
+
+    if !topics.is_empty() {
+        let placeholders = (first..first + topics.len())
+            .map(|index| format!("?{index}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        conditions.push(format!("{topic_column} IN ({placeholders})"));
+        args.extend(topics);
+    }
+
+    if let Some(start_ns) = start {
+        conditions.push(format!("messages.timestamp >= ?{}", first + args.len()));
+        args.push(Value::Integer(start_ns));
+    }
+
+    if let Some(stop_ns) = stop {
+        conditions.push(format!("messages.timestamp < ?{}", first + args.len()));
+        args.push(Value::Integer(stop_ns));
+    }
+
+    (conditions, args)
+}
+
+/// A message without its data, see [`Sqlite3Reader::scan_messages`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sqlite3MessageInfo {
+    pub id: MessageId,
+    /// Id of the connection, as returned by [`Sqlite3Reader::connections`].
+    pub topic_id: i64,
+    pub timestamp: i64,
+    /// Size of the data as stored.
+    pub size: usize,
+}
+
+/// Aggregates of the messages of one topic in one database file, see
+/// [`Sqlite3Reader::file_statistics`]. Sizes are those of the data as stored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sqlite3TopicAggregate {
+    pub topic: String,
+    pub count: u64,
+    pub bytes: u64,
+    pub min_size: u64,
+    pub max_size: u64,
+    pub size_squares: f64,
+    /// Timestamp of the first message.
+    pub first: i64,
+    /// Timestamp of the last message.
+    pub last: i64,
+    /// Smallest gap between consecutive messages, `None` for a single message.
+    pub min_gap: Option<i64>,
+    pub max_gap: Option<i64>,
+    pub gap_squares: f64,
+}
+
+/// A message row of a database file: `(topic id, timestamp, data, messages.id)`.
+pub type Sqlite3Row = (i64, i64, Vec<u8>, i64);
+
+/// Order in which the messages of a database file are fetched.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Direction {
+    /// Ascending `(timestamp, messages.id)`.
+    #[default]
+    Forward,
+    /// Descending `(timestamp, messages.id)`.
+    Backward,
+}
+
+impl Direction {
+    /// Keyset before the first row in this direction.
+    fn first_keyset(self) -> (i64, i64) {
+        match self {
+            Direction::Forward => (i64::MIN, i64::MIN),
+            Direction::Backward => (i64::MAX, i64::MAX),
+        }
+    }
+
+    /// Key ordering the message at `timestamp` of the file with the given index when
+    /// merging files. A min-heap yields the smallest key first, which has to be the
+    /// earliest message when reading forward and the latest one when reading backward.
+    fn merge_key(self, timestamp: i64, index: usize) -> (i64, usize) {
+        match self {
+            Direction::Forward => (timestamp, index),
+            // bitwise not reverses the order of integers without overflowing
+            Direction::Backward => (!timestamp, !index),
+        }
+    }
+}
+
+/// Query for the messages of some connections in one database file, see
+/// [`Sqlite3Reader::file_query`].
+///
+/// Rows are addressed by their keyset `(timestamp, messages.id)`, which the `timestamp_idx`
+/// index of rosbag2 databases serves in both directions.
+pub struct Sqlite3FileQuery<'a> {
+    conn: &'a Connection,
+    /// Conditions selecting the requested topics and time range.
+    conditions: Vec<String>,
+    /// Values of the query parameters following the keyset and row limit.
+    args: Vec<Value>,
+    /// Maps topic ids of this file to the ids of the requested connections.
+    topic_ids: HashMap<i64, i64>,
+    /// The file has none of the requested topics.
+    empty: bool,
+}
+
+impl<'a> Sqlite3FileQuery<'a> {
+    /// Fetches up to `limit` rows.
+    ///
+    /// [`Direction::Forward`] returns the rows with a keyset greater than `keyset` in
+    /// ascending order, [`Direction::Backward`] those with a smaller keyset in descending
+    /// order. Fetching stops early once the rows hold `max_bytes` of data.
+    pub fn fetch(
+        &self,
+        keyset: (i64, i64),
+        direction: Direction,
+        limit: i64,
+        max_bytes: usize,
+    ) -> Result<Vec<Sqlite3Row>> {
+        if self.empty {
+            return Ok(vec![]);
+        }
+        let mut stmt = self.statement(direction, DATA_COLUMN)?;
+        let mut rows = self.query(&mut stmt, keyset, limit)?;
+
+        let mut fetched = vec![];
+        let mut bytes = 0;
+        while let Some(row) = rows.next()? {
+            let data: Vec<u8> = row.get(2)?;
+            bytes += data.len();
+            fetched.push((self.topic_id(row)?, row.get(1)?, data, row.get(3)?));
+            if bytes >= max_bytes {
+                break;
+            }
+        }
+        Ok(fetched)
+    }
+
+    /// Prepares the query of rows `(topic id, timestamp, data, messages.id)` following the
+    /// keyset `(?1, ?2)` in `direction`, limited to `?3` rows.
+    fn statement(&self, direction: Direction, data: &str) -> Result<CachedStatement<'a>> {
+        let (comparison, order) = match direction {
+            Direction::Forward => (">", "ASC"),
+            Direction::Backward => ("<", "DESC"),
         };
+        let mut conditions = self.conditions.clone();
+        conditions.push(format!(
+            "(messages.timestamp, messages.id) {comparison} (?1, ?2)"
+        ));
+        let query = format!(
+            "SELECT messages.topic_id, messages.timestamp, {data}, messages.id FROM messages WHERE {} ORDER BY messages.timestamp {order}, messages.id {order} LIMIT ?3",
+            conditions.join(" AND ")
+        );
+        trace!(query, args = ?self.args, "preparing messages query");
+        Ok(self.conn.prepare_cached(&query)?)
+    }
+
+    fn query<'s>(
+        &self,
+        stmt: &'s mut Statement,
+        keyset: (i64, i64),
+        limit: i64,
+    ) -> Result<Rows<'s>> {
+        let keyset = [keyset.0, keyset.1, limit].map(Value::Integer);
+        Ok(stmt.query(params_from_iter(keyset.iter().chain(&self.args)))?)
+    }
+
+    /// Id of the requested connection the topic of `row` maps to.
+    fn topic_id(&self, row: &Row) -> Result<i64> {
+        let id: i64 = row.get(0)?;
+        Ok(self.topic_ids.get(&id).copied().unwrap_or(id))
     }
 }
 
-pub fn handle_messages<F: Fn((i64, i64, Vec<u8>)) -> Result<()>>(
-    mut stmt: Statement,
-    handle_func: F,
-) -> Result<()> {
-    let handler = stmt.query_map([], |row| {
-        let id = row.get::<_, i64>(0)?;
-        let timestamp = row.get(1)?;
-        let data = row.get(2)?;
-        let result = handle_func((id, timestamp, data));
-        if let Err(e) = result {
-            return Err(rusqlite::Error::InvalidParameterName(format!("error: {e}")));
+/// Iterator over `(topic id, timestamp, data)` rows of one database file, see
+/// [`Sqlite3Reader::file_messages`].
+pub struct Sqlite3FileMessages<'a> {
+    query: Sqlite3FileQuery<'a>,
+    direction: Direction,
+    /// `(timestamp, messages.id)` of the last row fetched, used as the keyset for the next page.
+    last: (i64, i64),
+    /// Row limit of the next page. It starts at one and grows up to [`PAGE_ROWS`], so that
+    /// merging many files only buffers a single row per file up front.
+    page_rows: i64,
+    buffer: VecDeque<(i64, i64, Vec<u8>)>,
+    exhausted: bool,
+}
+
+impl Sqlite3FileMessages<'_> {
+    fn fetch_page(&mut self) -> Result<()> {
+        let rows = self
+            .query
+            .fetch(self.last, self.direction, self.page_rows, PAGE_BYTES)?;
+
+        let fetched = rows.len() as i64;
+        let bytes = rows.iter().map(|row| row.2.len()).sum::<usize>();
+        for (id, timestamp, data, rowid) in rows {
+            self.last = (timestamp, rowid);
+            self.buffer.push_back((id, timestamp, data));
+        }
+        if bytes >= PAGE_BYTES {
+            return Ok(());
         }
+
+        if fetched < self.page_rows {
+            self.exhausted = true;
+        }
+        self.page_rows = (self.page_rows * 2).min(PAGE_ROWS);
         Ok(())
-    })?;
+    }
+}
+
+impl Iterator for Sqlite3FileMessages<'_> {
+    type Item = Result<(i64, i64, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() && !self.exhausted {
+            if let Err(e) = self.fetch_page() {
+                self.exhausted = true;
+                return Some(Err(e));
+            }
+        }
+        self.buffer.pop_front().map(Ok)
+    }
+}
+
+/// Iterator merging the messages of several database files into one stream in global
+/// timestamp order, see [`Sqlite3Reader::messages`].
+///
+/// Messages with equal timestamps are yielded in the order of their files, or in reverse
+/// order when reading backward.
+pub struct Sqlite3Messages<'a> {
+    sources: Vec<Sqlite3FileMessages<'a>>,
+    direction: Direction,
+    /// Source index of the next message of every source, ordered by
+    /// [`Direction::merge_key`].
+    heads: BinaryHeap<Reverse<((i64, usize), usize)>>,
+    pending: Vec<Option<(i64, i64, Vec<u8>)>>,
+    /// Sources that must be advanced before the next pick: all of them at first, then the
+    /// one whose head was handed out last.
+    stale: Vec<usize>,
+}
+
+impl<'a> Sqlite3Messages<'a> {
+    fn new(sources: Vec<Sqlite3FileMessages<'a>>, direction: Direction) -> Self {
+        let pending = sources.iter().map(|_| None).collect();
+        let stale = (0..sources.len()).rev().collect();
+        Sqlite3Messages {
+            sources,
+            direction,
+            heads: BinaryHeap::new(),
+            pending,
+            stale,
+        }
+    }
+
+    fn advance(&mut self, index: usize) -> Result<()> {
+        if let Some(message) = self.sources[index].next().transpose()? {
+            let key = self.direction.merge_key(message.1, index);
+            self.heads.push(Reverse((key, index)));
+            self.pending[index] = Some(message);
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Sqlite3Messages<'_> {
+    type Item = Result<(i64, i64, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(index) = self.stale.pop() {
+            if let Err(e) = self.advance(index) {
+                // a source that failed cannot be merged correctly anymore, end the stream
+                self.stale.clear();
+                self.heads.clear();
+                return Some(Err(e));
+            }
+        }
+
+        let Reverse((_, index)) = self.heads.pop()?;
+        self.stale.push(index);
+        self.pending[index].take().map(Ok)
+    }
+}
+
+/// Streams the rows of `queries` into `handle_row`, merged in `direction` order, together
+/// with the index of their file. `data` is the expression selected as the third column.
+fn merge_rows<F>(
+    queries: Vec<(usize, Sqlite3FileQuery)>,
+    direction: Direction,
+    data: &str,
+    mut handle_row: F,
+) -> Result<()>
+where
+    F: FnMut(usize, &Sqlite3FileQuery, &Row) -> Result<HandlerAction>,
+{
+    let mut statements = vec![];
+    for (index, query) in &queries {
+        statements.push((*index, query, query.statement(direction, data)?));
+    }
+    let mut sources = vec![];
+    for (index, query, stmt) in &mut statements {
+        // one query per file without a row limit, stepped through while merging
+        let rows = query.query(stmt, direction.first_keyset(), -1)?;
+        sources.push((*index, *query, rows));
+    }
 
-    for res in handler {
-        if let Err(e) = res {
-            println!("error: {e:?} when handle messages");
+    // the current row of every source stays in its statement until it is advanced
+    let mut heads = BinaryHeap::new();
+    for (source, (_, _, rows)) in sources.iter_mut().enumerate() {
+        if let Some(row) = rows.next()? {
+            heads.push(Reverse((direction.merge_key(row.get(1)?, source), source)));
+        }
+    }
+    while let Some(Reverse((_, source))) = heads.pop() {
+        let (file, query, rows) = &mut sources[source];
+        if let Some(row) = rows.get() {
+            if handle_row(*file, query, row)? == HandlerAction::Stop {
+                return Ok(());
+            }
+        }
+        if let Some(row) = rows.next()? {
+            heads.push(Reverse((direction.merge_key(row.get(1)?, source), source)));
+        }
+    }
+    Ok(())
+}
+
+/// A message row returned by [`Sqlite3Tail::new_messages`]: `(topic id, timestamp, data)`,
+/// without the data if the message was not selected.
+pub type Sqlite3TailRow = (i64, i64, Option<Vec<u8>>);
+
+/// Reads the rows appended to a database file that is still being written, see
+/// [`Follow`].
+///
+/// Rows are read in insertion order, i.e. by `messages.id`, and every call only returns
+/// what was added since the previous one.
+pub struct Sqlite3Tail {
+    conn: Connection,
+    /// `topics.id` of the last topic returned by [`Sqlite3Tail::new_topics`].
+    last_topic: i64,
+    /// `messages.id` of the last message returned by [`Sqlite3Tail::new_messages`].
+    last_row: i64,
+}
+
+impl Sqlite3Tail {
+    /// Opens the database file at `path` read-only.
+    ///
+    /// Fails with [`Rosbag2Error::SchemaMismatch`] while the writer has not created the
+    /// tables yet.
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        for table in ["messages", "topics"] {
+            if !table_exists(&conn, table)? {
+                return Err(Rosbag2Error::SchemaMismatch(format!(
+                    "database {path:?} has no {table} table yet"
+                )));
+            }
+        }
+        Ok(Sqlite3Tail {
+            conn,
+            last_topic: 0,
+            last_row: 0,
+        })
+    }
+
+    /// Returns the topics added since the last call, as connections with their ids in
+    /// this file.
+    pub fn new_topics(&mut self) -> Result<Vec<TopicConnection>> {
+        let topics = read_topics(&self.conn, self.last_topic)?;
+        if let Some(topic) = topics.last() {
+            self.last_topic = topic.id as i64;
+        }
+        Ok(topics)
+    }
+
+    /// Returns up to `limit` messages added since the last call, in insertion order.
+    ///
+    /// Only messages of `topics` within `[start, stop)` come with their data, the data of
+    /// all other messages is `None`. Messages of topics that were added after the last
+    /// call to [`Sqlite3Tail::new_topics`] are held back until it was called again.
+    pub fn new_messages(
+        &mut self,
+        topics: &[i64],
+        start: Option<i64>,
+        stop: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<Sqlite3TailRow>> {
+        // ?1 and ?2 are the last row and the row limit
+        let (mut conditions, args) = messages_conditions("topic_id", vec![], start, stop, 3);
+        let topics = topics
+            .iter()
+            .map(i64::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        conditions.push(format!("messages.topic_id IN ({topics})"));
+
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT messages.id, messages.topic_id, messages.timestamp, CASE WHEN {} THEN messages.data END FROM messages WHERE messages.id > ?1 ORDER BY messages.id LIMIT ?2",
+            conditions.join(" AND ")
+        ))?;
+        let keyset = [self.last_row, limit].map(Value::Integer);
+        let mut rows = stmt.query(params_from_iter(keyset.iter().chain(&args)))?;
+
+        let mut messages = vec![];
+        while let Some(row) = rows.next()? {
+            let topic_id: i64 = row.get(1)?;
+            if topic_id > self.last_topic {
+                break;
+            }
+            self.last_row = row.get(0)?;
+            messages.push((topic_id, row.get(2)?, row.get(3)?));
+        }
+        Ok(messages)
+    }
+}
+
+/// Runs `handle_func` on every row of a statement prepared by
+/// [`Sqlite3Reader::messages_statement`].
+///
+/// Reading stops early when the handler returns [`HandlerAction::Stop`]. Errors of the
+/// handler and of decoding rows end the read and are returned to the caller.
+pub fn handle_messages<F, R>(mut stmt: Statement, mut handle_func: F) -> Result<()>
+where
+    F: FnMut((i64, i64, Vec<u8>)) -> Result<R>,
+    R: Into<HandlerAction>,
+{
+    let mut rows = stmt.raw_query();
+    while let Some(row) = rows.next()? {
+        let id = row.get::<_, i64>(0)?;
+        let timestamp = row.get(1)?;
+        let data = row.get(2)?;
+        if handle_func((id, timestamp, data))?.into() == HandlerAction::Stop {
+            break;
         }
     }
     Ok(())
@@ -0,0 +1,153 @@
+//! Message statistics computed by SQL aggregates, see [`Reader::statistics`].
+
+use crate::*;
+use std::collections::BTreeMap;
+
+/// Statistics of the messages of a bag selected by a [`ReadFilter`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BagStatistics {
+    pub message_count: u64,
+    /// Bytes of message data, as stored.
+    pub bytes: u64,
+    /// Timestamp of the first message, `None` without messages.
+    pub first: Option<Time>,
+    /// Timestamp of the last message, `None` without messages.
+    pub last: Option<Time>,
+    /// Messages per second over all topics, `None` unless messages span some time.
+    pub frequency: Option<f64>,
+    /// Statistics of the topics with messages, ordered by topic name.
+    pub topics: Vec<TopicStatistics>,
+}
+
+/// Statistics of the messages of one topic, over all of its selected connections.
+///
+/// Sizes are those of the data as stored, i.e. compressed for message compressed bags.
+/// Gaps are the times between consecutive messages of the topic; their statistics are
+/// `None` for a single message. The statistics of a single connection are those of a
+/// filter selecting just it, see [`ReadFilter::connections`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct TopicStatistics {
+    pub topic: String,
+    /// Message type of the topic, `None` when its connections have different types.
+    pub msgtype: Option<String>,
+    /// Ids of the selected connections of the topic, as in [`Reader::connections`].
+    pub connections: Vec<i32>,
+    pub message_count: u64,
+    pub bytes: u64,
+    pub min_size: u64,
+    pub max_size: u64,
+    pub mean_size: f64,
+    /// Standard deviation of the sizes.
+    pub size_stddev: f64,
+    pub first: Time,
+    pub last: Time,
+    /// Messages per second, `None` unless the messages span some time.
+    pub frequency: Option<f64>,
+    pub min_gap: Option<Duration>,
+    pub max_gap: Option<Duration>,
+    pub mean_gap: Option<Duration>,
+    /// Standard deviation of the gaps.
+    pub jitter: Option<Duration>,
+}
+
+/// Combines the per-file aggregates of the files of a bag into its statistics.
+///
+/// The gap between the last message of a topic in one file and its first message in the
+/// next is added to the gaps of the topic. Split files are expected to follow each other
+/// in time, as rosbag2 writes them.
+pub(crate) fn bag_statistics(
+    connections: &[TopicConnection],
+    aggregates: Vec<Sqlite3TopicAggregate>,
+) -> BagStatistics {
+    let mut per_topic = BTreeMap::<String, Vec<Sqlite3TopicAggregate>>::new();
+    for aggregate in aggregates {
+        per_topic
+            .entry(aggregate.topic.clone())
+            .or_default()
+            .push(aggregate);
+    }
+
+    let mut topics = vec![];
+    for (topic, mut files) in per_topic {
+        files.sort_by_key(|file| file.first);
+        let mut files = files.into_iter();
+        let Some(mut total) = files.next() else {
+            continue;
+        };
+        for file in files {
+            let gap = file.first.saturating_sub(total.last);
+            total.min_gap = [total.min_gap, file.min_gap, Some(gap)]
+                .into_iter()
+                .flatten()
+                .min();
+            total.max_gap = [total.max_gap, file.max_gap, Some(gap)]
+                .into_iter()
+                .flatten()
+                .max();
+            total.gap_squares += file.gap_squares + (gap as f64) * (gap as f64);
+            total.count += file.count;
+            total.bytes += file.bytes;
+            total.min_size = total.min_size.min(file.min_size);
+            total.max_size = total.max_size.max(file.max_size);
+            total.size_squares += file.size_squares;
+            total.last = total.last.max(file.last);
+        }
+
+        let topic_connections = connections
+            .iter()
+            .filter(|c| c.topic == topic)
+            .collect::<Vec<_>>();
+        let msgtype = topic_connections
+            .first()
+            .map(|c| &c.msgtype)
+            .filter(|msgtype| topic_connections.iter().all(|c| c.msgtype == **msgtype))
+            .cloned();
+        let mean_size = total.bytes as f64 / total.count as f64;
+        let gaps = total.count - 1;
+        let mean_gap =
+            (gaps > 0).then(|| total.last.saturating_sub(total.first) as f64 / gaps as f64);
+        topics.push(TopicStatistics {
+            topic,
+            msgtype,
+            connections: topic_connections.iter().map(|c| c.id).collect(),
+            message_count: total.count,
+            bytes: total.bytes,
+            min_size: total.min_size,
+            max_size: total.max_size,
+            mean_size,
+            size_stddev: stddev(total.size_squares / total.count as f64, mean_size),
+            first: Time(total.first),
+            last: Time(total.last),
+            frequency: frequency(total.count, total.first, total.last),
+            min_gap: total.min_gap.map(Duration),
+            max_gap: total.max_gap.map(Duration),
+            mean_gap: mean_gap.map(|mean| Duration(mean.round() as i64)),
+            jitter: mean_gap
+                .map(|mean| Duration(stddev(total.gap_squares / gaps as f64, mean).round() as i64)),
+        });
+    }
+
+    let message_count = topics.iter().map(|t| t.message_count).sum();
+    let first = topics.iter().map(|t| t.first).min();
+    let last = topics.iter().map(|t| t.last).max();
+    BagStatistics {
+        message_count,
+        bytes: topics.iter().map(|t| t.bytes).sum(),
+        first,
+        last,
+        frequency: first
+            .zip(last)
+            .and_then(|(first, last)| frequency(message_count, first.0, last.0)),
+        topics,
+    }
+}
+
+/// Standard deviation from the mean of the squares and the mean.
+fn stddev(mean_square: f64, mean: f64) -> f64 {
+    // rounding may make the variance of equal values slightly negative
+    (mean_square - mean * mean).max(0.0).sqrt()
+}
+
+fn frequency(count: u64, first: i64, last: i64) -> Option<f64> {
+    (last > first).then(|| (count - 1) as f64 / (last.saturating_sub(first) as f64 / 1e9))
+}
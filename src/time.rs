@@ -0,0 +1,235 @@
+//! Timestamps and durations of messages.
+//!
+//! Bags store both as signed nanoseconds: timestamps since the Unix epoch, which is what
+//! ROS uses for the system clock. [`Time`] and [`Duration`] wrap those values and convert
+//! to and from [`SystemTime`], the `builtin_interfaces` messages and, with the `chrono`
+//! feature, chrono types. Conversions that do not fit the target fail with
+//! [`TryFromIntError`].
+
+use std::num::TryFromIntError;
+use std::ops::{Add, Sub};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub(crate) const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+/// A point in time, in nanoseconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Time(pub i64);
+
+/// A signed span of time in nanoseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Duration(pub i64);
+
+/// `builtin_interfaces/msg/Time`. `nanosec` is below one second, also before the epoch.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TimeMsg {
+    pub sec: i32,
+    pub nanosec: u32,
+}
+
+/// `builtin_interfaces/msg/Duration`. `nanosec` is below one second, also for negative
+/// durations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct DurationMsg {
+    pub sec: i32,
+    pub nanosec: u32,
+}
+
+impl Time {
+    pub fn as_nanos(self) -> i64 {
+        self.0
+    }
+
+    /// Adds `duration`, returning `None` on overflow.
+    pub fn checked_add(self, duration: Duration) -> Option<Time> {
+        self.0.checked_add(duration.0).map(Time)
+    }
+}
+
+impl Duration {
+    pub fn as_nanos(self) -> i64 {
+        self.0
+    }
+
+    /// Adds `duration`, returning `None` on overflow.
+    pub fn checked_add(self, duration: Duration) -> Option<Duration> {
+        self.0.checked_add(duration.0).map(Duration)
+    }
+}
+
+impl From<i64> for Time {
+    fn from(nanos: i64) -> Self {
+        Time(nanos)
+    }
+}
+
+impl From<Time> for i64 {
+    fn from(time: Time) -> Self {
+        time.0
+    }
+}
+
+impl From<i64> for Duration {
+    fn from(nanos: i64) -> Self {
+        Duration(nanos)
+    }
+}
+
+impl From<Duration> for i64 {
+    fn from(duration: Duration) -> Self {
+        duration.0
+    }
+}
+
+impl Add<Duration> for Time {
+    type Output = Time;
+
+    fn add(self, rhs: Duration) -> Time {
+        Time(self.0 + rhs.0)
+    }
+}
+
+impl Sub<Duration> for Time {
+    type Output = Time;
+
+    fn sub(self, rhs: Duration) -> Time {
+        Time(self.0 - rhs.0)
+    }
+}
+
+impl Sub for Time {
+    type Output = Duration;
+
+    fn sub(self, rhs: Time) -> Duration {
+        Duration(self.0 - rhs.0)
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, rhs: Duration) -> Duration {
+        Duration(self.0 + rhs.0)
+    }
+}
+
+impl Sub for Duration {
+    type Output = Duration;
+
+    fn sub(self, rhs: Duration) -> Duration {
+        Duration(self.0 - rhs.0)
+    }
+}
+
+/// Splits nanoseconds into seconds and nanoseconds below one second, like rclcpp.
+fn split_nanos(nanos: i64) -> Result<(i32, u32), TryFromIntError> {
+    let sec = i32::try_from(nanos.div_euclid(NANOS_PER_SEC))?;
+    Ok((sec, nanos.rem_euclid(NANOS_PER_SEC) as u32))
+}
+
+impl From<TimeMsg> for Time {
+    fn from(msg: TimeMsg) -> Self {
+        Time(msg.sec as i64 * NANOS_PER_SEC + msg.nanosec as i64)
+    }
+}
+
+impl TryFrom<Time> for TimeMsg {
+    type Error = TryFromIntError;
+
+    /// Fails for times the 32 bit seconds cannot hold, i.e. after 2038.
+    fn try_from(time: Time) -> Result<Self, Self::Error> {
+        let (sec, nanosec) = split_nanos(time.0)?;
+        Ok(TimeMsg { sec, nanosec })
+    }
+}
+
+impl From<DurationMsg> for Duration {
+    fn from(msg: DurationMsg) -> Self {
+        Duration(msg.sec as i64 * NANOS_PER_SEC + msg.nanosec as i64)
+    }
+}
+
+impl TryFrom<Duration> for DurationMsg {
+    type Error = TryFromIntError;
+
+    fn try_from(duration: Duration) -> Result<Self, Self::Error> {
+        let (sec, nanosec) = split_nanos(duration.0)?;
+        Ok(DurationMsg { sec, nanosec })
+    }
+}
+
+impl From<Time> for SystemTime {
+    fn from(time: Time) -> Self {
+        let offset = std::time::Duration::from_nanos(time.0.unsigned_abs());
+        if time.0 >= 0 {
+            UNIX_EPOCH + offset
+        } else {
+            UNIX_EPOCH - offset
+        }
+    }
+}
+
+impl TryFrom<SystemTime> for Time {
+    type Error = TryFromIntError;
+
+    /// Fails for times more than 292 years away from the epoch.
+    fn try_from(time: SystemTime) -> Result<Self, Self::Error> {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(offset) => Ok(Time(i64::try_from(offset.as_nanos())?)),
+            Err(e) => Ok(Time(-i64::try_from(e.duration().as_nanos())?)),
+        }
+    }
+}
+
+impl TryFrom<Duration> for std::time::Duration {
+    type Error = TryFromIntError;
+
+    /// Fails for negative durations.
+    fn try_from(duration: Duration) -> Result<Self, Self::Error> {
+        Ok(std::time::Duration::from_nanos(u64::try_from(duration.0)?))
+    }
+}
+
+impl TryFrom<std::time::Duration> for Duration {
+    type Error = TryFromIntError;
+
+    fn try_from(duration: std::time::Duration) -> Result<Self, Self::Error> {
+        Ok(Duration(i64::try_from(duration.as_nanos())?))
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl From<Time> for chrono::DateTime<chrono::Utc> {
+    fn from(time: Time) -> Self {
+        chrono::DateTime::from_timestamp_nanos(time.0)
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl TryFrom<chrono::DateTime<chrono::Utc>> for Time {
+    type Error = TryFromIntError;
+
+    fn try_from(time: chrono::DateTime<chrono::Utc>) -> Result<Self, Self::Error> {
+        let nanos = time.timestamp() as i128 * NANOS_PER_SEC as i128
+            + time.timestamp_subsec_nanos() as i128;
+        Ok(Time(i64::try_from(nanos)?))
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl From<Duration> for chrono::TimeDelta {
+    fn from(duration: Duration) -> Self {
+        chrono::TimeDelta::nanoseconds(duration.0)
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl TryFrom<chrono::TimeDelta> for Duration {
+    type Error = TryFromIntError;
+
+    fn try_from(duration: chrono::TimeDelta) -> Result<Self, Self::Error> {
+        let nanos = duration.num_seconds() as i128 * NANOS_PER_SEC as i128
+            + duration.subsec_nanos() as i128;
+        Ok(Duration(i64::try_from(nanos)?))
+    }
+}
@@ -1,10 +1,10 @@
 use crate::*;
-use anyhow::Result;
 use rusqlite::params;
 use rusqlite::Connection;
 use std::collections::HashMap;
 use std::fs::File;
 use std::path::{Path, PathBuf};
+use tracing::{debug, debug_span, trace};
 
 /// This class implements writing of rosbag2 files in version 5
 pub struct Writer {
@@ -24,10 +24,11 @@ impl Writer {
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
         let path = path.as_ref().to_path_buf();
         let metapath = path.join("metadata.yaml");
-        let dbpath = path.join(format!(
-            "{}.db3",
-            path.file_name().unwrap().to_str().unwrap()
-        ));
+        // paths such as `.` have no file name to derive the database name from
+        let name = path
+            .file_name()
+            .map_or("rosbag2".into(), |name| name.to_string_lossy());
+        let dbpath = path.join(format!("{name}.db3"));
 
         Writer {
             path,
@@ -47,14 +48,12 @@ impl Writer {
     /// Rosbag2 <https://github.com/ros2/rosbag2/blob/humble/rosbag2_storage_default_plugins/src/rosbag2_storage_default_plugins/sqlite/sqlite_storage.cpp#L360C13-L360C13>
     pub fn open(&mut self) -> Result<()> {
         if self.dbpath.exists() {
-            return Err(anyhow::anyhow!(
-                "Database file {:?} already exists.",
-                self.dbpath
-            ));
+            return Err(Rosbag2Error::AlreadyExists(self.dbpath.clone()));
         }
 
         std::fs::create_dir_all(&self.path)?;
 
+        debug!(path = %self.dbpath.display(), "creating database");
         let conn = Connection::open(&self.dbpath)?;
 
         // TODO: add support for beyond humble ros2bag
@@ -98,11 +97,7 @@ impl Writer {
         serialization_format: &str,
         offered_qos_profiles: &str,
     ) -> Result<TopicConnection> {
-        if self.conn.is_none() {
-            return Err(anyhow::anyhow!("Bag was not opened."));
-        }
-
-        let conn = self.conn.as_ref().unwrap();
+        let conn = self.conn.as_ref().ok_or(Rosbag2Error::NotOpen)?;
 
         let new_id = self.connections.len() as i32 + 1;
         let new_connection = TopicConnection {
@@ -113,6 +108,7 @@ impl Writer {
             ext: ConnectionExt {
                 serialization_format: serialization_format.to_string(),
                 offered_qos_profiles: offered_qos_profiles.to_string(),
+                type_description_hash: String::new(),
             },
             // owner field is omitted in Rust
         };
@@ -122,12 +118,13 @@ impl Writer {
             .iter()
             .any(|conn| conn.topic == topic && conn.msgtype == msgtype)
         {
-            return Err(anyhow::anyhow!(
-                "Connection can only be added once: {:?}",
-                new_connection
-            ));
+            return Err(Rosbag2Error::DuplicateConnection {
+                topic: topic.to_string(),
+                msgtype: msgtype.to_string(),
+            });
         }
 
+        debug!(id = new_id, topic, msgtype, "adding connection");
         self.connections.push(new_connection.clone());
         self.counts.insert(new_id, 0);
         conn.execute(
@@ -144,23 +141,33 @@ impl Writer {
         Ok(new_connection)
     }
 
+    /// Adds a connection offered with typed QoS profiles, written in the format of
+    /// version 5 bags.
+    pub fn add_connection_with_qos(
+        &mut self,
+        topic: &str,
+        msgtype: &str,
+        serialization_format: &str,
+        offered_qos_profiles: &[QosProfile],
+    ) -> Result<TopicConnection> {
+        let offered_qos_profiles =
+            QosProfile::format_list(offered_qos_profiles, QosFormat::Humble)?;
+        self.add_connection(topic, msgtype, serialization_format, &offered_qos_profiles)
+    }
+
     pub fn write(
         &mut self,
         connection: &TopicConnection,
-        timestamp: i64,
+        timestamp: impl Into<Time>,
         data: &[u8],
     ) -> Result<()> {
-        if self.conn.is_none() {
-            return Err(anyhow::anyhow!("Bag was not opened."));
-        }
-
-        let conn = self.conn.as_ref().unwrap();
+        let conn = self.conn.as_ref().ok_or(Rosbag2Error::NotOpen)?;
 
         if !self.connections.contains(connection) {
-            return Err(anyhow::anyhow!(
-                "Tried to write to unknown connection {:?}",
-                connection
-            ));
+            return Err(Rosbag2Error::UnknownConnection {
+                id: connection.id,
+                topic: connection.topic.clone(),
+            });
         }
 
         // TODO: Handle compression if needed
@@ -169,6 +176,13 @@ impl Writer {
         //     // data_to_write = self.compressor.compress(data)?;
         // }
 
+        let timestamp = timestamp.into().as_nanos();
+        trace!(
+            topic = connection.topic,
+            timestamp,
+            size = data.len(),
+            "writing message"
+        );
         conn.execute(
             "INSERT INTO messages (topic_id, timestamp, data) VALUES(?1, ?2, ?3)",
             params![
@@ -187,12 +201,15 @@ impl Writer {
 
     pub fn close(&mut self) -> Result<()> {
         if let Some(conn) = self.conn.take() {
+            let _span = debug_span!("close", path = %self.path.display()).entered();
             // Calculate duration, start time, and message count
-            let (duration, start, count) = conn.query_row(
+            // Both are NULL for a bag without messages
+            let (duration, start, count): (Option<i64>, Option<i64>, i32) = conn.query_row(
                 "SELECT max(timestamp) - min(timestamp), min(timestamp), count(*) FROM messages",
                 [],
                 |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
             )?;
+            let (duration, start) = (duration.unwrap_or(0), start.unwrap_or(0));
 
             // Commit and optimize the database
             conn.execute("PRAGMA optimize", [])?;
@@ -203,6 +220,17 @@ impl Writer {
             let metadata = BagFileInfo {
                 rosbag2_bagfile_information: self.generate_metadata(duration, start, count)?,
             };
+
+            // Store it in the database as well, so the bag can be opened without metadata.yaml
+            conn.execute(
+                "INSERT INTO metadata (metadata_version, metadata) VALUES (?1, ?2)",
+                params![
+                    metadata.rosbag2_bagfile_information.version,
+                    serde_yaml::to_string(&metadata)?
+                ],
+            )?;
+
+            debug!(messages = count, "writing metadata");
             let file = File::create(&self.metapath)?;
             serde_yaml::to_writer(file, &metadata)?;
         }
@@ -222,14 +250,20 @@ impl Writer {
                     type_: conn.msgtype.clone(),
                     serialization_format: conn.ext.serialization_format.clone(),
                     offered_qos_profiles: conn.ext.offered_qos_profiles.clone(),
-                    // type_description_hash: conn.digest.clone(), // TODO: implement type_description_hash
+                    type_description_hash: conn.ext.type_description_hash.clone(),
                 },
             })
             .collect();
 
+        let relative_path = self
+            .dbpath
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
         // Generate files information
         let files = vec![FileInformation {
-            path: self.dbpath.to_str().unwrap().to_string(),
+            path: relative_path.clone(),
             starting_time: StartingTime {
                 nanoseconds_since_epoch: start,
             },
@@ -242,13 +276,7 @@ impl Writer {
         Ok(Metadata {
             version: 5,
             storage_identifier: "sqlite3".to_string(),
-            relative_file_paths: vec![self
-                .dbpath
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_string()],
+            relative_file_paths: vec![relative_path],
             starting_time: StartingTime {
                 nanoseconds_since_epoch: start,
             },
@@ -0,0 +1,94 @@
+#![cfg(feature = "tokio")]
+
+use anyhow::Result;
+use rosbag2_rs::{AsyncReader, ReadFilter, Reader, Rosbag2Error, Time, Writer};
+use std::time::Duration;
+use tempfile::tempdir;
+use tokio::time::timeout;
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn test_async_messages() -> Result<()> {
+    let dir = tempdir()?;
+
+    let mut writer = Writer::new(dir.path());
+    writer.open()?;
+    let imu = writer.add_connection("/imu", "sensor_msgs/msg/Imu", "cdr", "")?;
+    let odom = writer.add_connection("/odom", "nav_msgs/msg/Odometry", "cdr", "")?;
+    for i in 0..100 {
+        writer.write(if i % 2 == 0 { &imu } else { &odom }, i, &[i as u8])?;
+    }
+    writer.close()?;
+
+    // a channel smaller than the bag makes the reading thread wait for the consumer
+    let reader = AsyncReader::new(dir.path()).await?.with_capacity(4);
+    assert_eq!(reader.connections.len(), 2);
+    let filter = ReadFilter {
+        topics: ["/imu".to_string()].into(),
+        start: Some(Time(10)),
+        ..Default::default()
+    };
+    let messages = reader
+        .messages(filter.clone())
+        .await
+        .collect::<Result<Vec<_>, _>>()
+        .await?;
+    let expected = Reader::new(dir.path())?
+        .messages(&filter)?
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(messages.len(), 45);
+    assert_eq!(messages, expected);
+
+    // dropping a stream early stops its reading thread
+    let first = reader.messages(ReadFilter::default()).await.next().await;
+    let first = first.transpose()?.unwrap();
+    assert_eq!(first.connection.id, imu.id);
+    assert_eq!((first.timestamp.0, first.data), (0, vec![0]));
+    let all = reader
+        .messages(ReadFilter::default())
+        .await
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(all.len(), 100);
+
+    assert!(matches!(
+        AsyncReader::new(dir.path().join("missing")).await,
+        Err(Rosbag2Error::Io(_))
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_async_concurrent_streams() -> Result<()> {
+    let dir = tempdir()?;
+
+    let mut writer = Writer::new(dir.path());
+    writer.open()?;
+    let imu = writer.add_connection("/imu", "sensor_msgs/msg/Imu", "cdr", "")?;
+    for i in 0..20 {
+        writer.write(&imu, i, &[i as u8])?;
+    }
+    writer.close()?;
+
+    // both reading threads wait on their full channels while the streams are consumed
+    // in turns, which requires each stream to read on its own
+    let reader = AsyncReader::new(dir.path()).await?.with_capacity(1);
+    let mut forward = reader.messages(ReadFilter::default()).await;
+    let reverse = ReadFilter {
+        reverse: true,
+        ..Default::default()
+    };
+    let mut backward = reader.messages(reverse).await;
+    let read = async {
+        let mut pairs = vec![];
+        while let (Some(a), Some(b)) = (forward.next().await, backward.next().await) {
+            pairs.push((a?.timestamp.0, b?.timestamp.0));
+        }
+        Ok::<_, Rosbag2Error>(pairs)
+    };
+    let pairs = timeout(Duration::from_secs(10), read).await??;
+    assert_eq!(pairs, (0..20).map(|i| (i, 19 - i)).collect::<Vec<_>>());
+
+    Ok(())
+}
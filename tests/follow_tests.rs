@@ -0,0 +1,127 @@
+use anyhow::Result;
+use rosbag2_rs::{FollowOptions, ReadFilter, Reader, Writer};
+use rusqlite::Connection;
+use std::fs;
+use std::thread;
+use std::time::Duration;
+use tempfile::tempdir;
+
+#[test]
+fn test_follow_recording() -> Result<()> {
+    let dir = tempdir()?;
+    let bag_path = dir.path().join("bag");
+    let split_path = dir.path().join("bag_1");
+
+    // start following before the recording starts
+    let options = FollowOptions {
+        poll_interval: Duration::from_millis(5),
+        timeout: Some(Duration::from_secs(10)),
+    };
+    let mut follow = Reader::follow(&bag_path, ReadFilter::default(), options);
+
+    let recorder = {
+        let (bag_path, split_path) = (bag_path.clone(), split_path.clone());
+        thread::spawn(move || -> rosbag2_rs::Result<()> {
+            let mut writer = Writer::new(&bag_path);
+            writer.open()?;
+            let a = writer.add_connection("/a", "std_msgs/msg/Int64", "cdr", "")?;
+            for i in 0..10 {
+                writer.write(&a, i, &[i as u8])?;
+                thread::sleep(Duration::from_millis(2));
+            }
+
+            // the second file registers the topics in another order, so their ids differ
+            let mut split = Writer::new(&split_path);
+            split.open()?;
+            let b = split.add_connection("/b", "std_msgs/msg/Int64", "cdr", "")?;
+            let a = split.add_connection("/a", "std_msgs/msg/Int64", "cdr", "")?;
+            split.write(&b, 10, &[10])?;
+            split.write(&a, 11, &[11])?;
+            split.close()?;
+            fs::rename(&split.dbpath, bag_path.join("bag_1.db3"))?;
+
+            thread::sleep(Duration::from_millis(20));
+            writer.close()
+        })
+    };
+
+    let messages = follow.by_ref().collect::<Result<Vec<_>, _>>()?;
+    recorder.join().unwrap()?;
+    assert!(follow.closed());
+
+    let connections = follow.connections();
+    assert_eq!(connections.len(), 2);
+    assert_eq!(
+        (connections[0].topic.as_str(), connections[0].msgcount),
+        ("/a", 11)
+    );
+    assert_eq!(
+        (connections[1].topic.as_str(), connections[1].msgcount),
+        ("/b", 1)
+    );
+    let mut expected = (0..10).map(|i| (1, i, vec![i as u8])).collect::<Vec<_>>();
+    expected.extend([(2, 10, vec![10]), (1, 11, vec![11])]);
+    assert_eq!(
+        messages
+            .into_iter()
+            .map(|m| (m.connection.id, m.timestamp.0, m.data))
+            .collect::<Vec<_>>(),
+        expected
+    );
+
+    // a bag that is not closed is followed until the timeout
+    fs::remove_file(bag_path.join("metadata.yaml"))?;
+    let filter = ReadFilter {
+        topics: ["/b".to_string()].into(),
+        ..Default::default()
+    };
+    let options = FollowOptions {
+        poll_interval: Duration::from_millis(5),
+        timeout: Some(Duration::from_millis(50)),
+    };
+    let mut follow = Reader::follow(&bag_path, filter, options);
+    let messages = follow.by_ref().collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].connection.topic, "/b");
+    assert_eq!(
+        (messages[0].timestamp.0, messages[0].data.as_slice()),
+        (10, &[10][..])
+    );
+    assert!(!follow.closed());
+
+    Ok(())
+}
+
+#[test]
+fn test_follow_orphan_topic_id() -> Result<()> {
+    let dir = tempdir()?;
+    let bag_path = dir.path().join("bag");
+    let mut writer = Writer::new(&bag_path);
+    writer.open()?;
+    let a = writer.add_connection("/a", "std_msgs/msg/Int64", "cdr", "")?;
+    let b = writer.add_connection("/b", "std_msgs/msg/Int64", "cdr", "")?;
+    writer.write(&a, 0, &[0])?;
+    writer.write(&b, 1, &[1])?;
+    writer.write(&a, 2, &[2])?;
+    writer.close()?;
+
+    // messages whose topic is missing from the topics table are skipped
+    let conn = Connection::open(&writer.dbpath)?;
+    conn.execute("DELETE FROM topics WHERE name = '/a'", [])?;
+    drop(conn);
+
+    let mut follow = Reader::follow(&bag_path, ReadFilter::default(), FollowOptions::default());
+    let messages = follow.by_ref().collect::<Result<Vec<_>, _>>()?;
+    assert!(follow.closed());
+    assert_eq!(follow.connections().len(), 1);
+    assert_eq!(follow.connections()[0].msgcount, 1);
+    assert_eq!(
+        messages
+            .into_iter()
+            .map(|m| (m.connection.topic.clone(), m.timestamp.0))
+            .collect::<Vec<_>>(),
+        vec![("/b".to_string(), 1)]
+    );
+
+    Ok(())
+}
@@ -0,0 +1,178 @@
+use anyhow::Result;
+use rosbag2_rs::{BagFileInfo, QosProfile, ReadFilter, Reader, Writer};
+use std::fs;
+use tempfile::tempdir;
+
+const JAZZY_METADATA: &str = r#"
+rosbag2_bagfile_information:
+  version: 9
+  storage_identifier: sqlite3
+  duration:
+    nanoseconds: 900000000
+  starting_time:
+    nanoseconds_since_epoch: 1700000000000000000
+  message_count: 10
+  topics_with_message_count:
+    - topic_metadata:
+        name: /chatter
+        type: std_msgs/msg/String
+        serialization_format: cdr
+        offered_qos_profiles:
+          - history: keep_last
+            depth: 10
+            reliability: reliable
+            durability: volatile
+            deadline:
+              sec: 9223372036
+              nsec: 854775807
+            lifespan:
+              sec: 9223372036
+              nsec: 854775807
+            liveliness: automatic
+            liveliness_lease_duration:
+              sec: 9223372036
+              nsec: 854775807
+            avoid_ros_namespace_conventions: false
+        type_description_hash: RIHS01_df668c740482bbd48fb39d76a70dfd4bd59db1288021743503259e948f6b1a18
+      message_count: 10
+  compression_format: ""
+  compression_mode: ""
+  relative_file_paths:
+    - jazzy_0.db3
+  files:
+    - path: jazzy_0.db3
+      starting_time:
+        nanoseconds_since_epoch: 1700000000000000000
+      duration:
+        nanoseconds: 900000000
+      message_count: 10
+  custom_data: ~
+  ros_distro: jazzy
+"#;
+
+const FOXY_METADATA: &str = r#"
+rosbag2_bagfile_information:
+  version: 4
+  storage_identifier: sqlite3
+  relative_file_paths:
+    - foxy_0.db3
+  duration:
+    nanoseconds: 0
+  starting_time:
+    nanoseconds_since_epoch: 0
+  message_count: 0
+  topics_with_message_count:
+    - topic_metadata:
+        name: /rosout
+        type: rcl_interfaces/msg/Log
+        serialization_format: cdr
+        offered_qos_profiles: ""
+      message_count: 0
+  compression_format: ""
+  compression_mode: ""
+"#;
+
+#[test]
+fn test_deserialize_metadata_versions() -> Result<()> {
+    let jazzy: BagFileInfo = serde_yaml::from_str(JAZZY_METADATA)?;
+    let metadata = jazzy.rosbag2_bagfile_information;
+    assert_eq!(metadata.version, 9);
+    assert_eq!(metadata.ros_distro, "jazzy");
+    assert!(metadata.custom_data.is_empty());
+    assert_eq!(metadata.files.len(), 1);
+    let topic = &metadata.topics_with_message_count[0].topic_metadata;
+    assert!(topic.type_description_hash.starts_with("RIHS01_"));
+    assert!(topic.offered_qos_profiles.contains("history: keep_last"));
+
+    let foxy: BagFileInfo = serde_yaml::from_str(FOXY_METADATA)?;
+    let metadata = foxy.rosbag2_bagfile_information;
+    assert_eq!(metadata.version, 4);
+    assert!(metadata.files.is_empty());
+    assert!(metadata.ros_distro.is_empty());
+    assert!(metadata.topics_with_message_count[0]
+        .topic_metadata
+        .type_description_hash
+        .is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_serialize_metadata_versions() -> Result<()> {
+    let jazzy: BagFileInfo = serde_yaml::from_str(JAZZY_METADATA)?;
+
+    // writing version 9 keeps the QoS profiles a sequence
+    let yaml = serde_yaml::to_string(&jazzy)?;
+    let value: serde_yaml::Value = serde_yaml::from_str(&yaml)?;
+    let topic =
+        &value["rosbag2_bagfile_information"]["topics_with_message_count"][0]["topic_metadata"];
+    assert!(topic["offered_qos_profiles"].is_sequence());
+    assert_eq!(topic["offered_qos_profiles"][0]["depth"], 10);
+    assert!(topic["type_description_hash"].is_string());
+
+    // an older version drops the fields it does not know and stores QoS as a string, in
+    // the encoding of that version
+    let jazzy_profiles = QosProfile::parse_list(
+        &jazzy.rosbag2_bagfile_information.topics_with_message_count[0]
+            .topic_metadata
+            .offered_qos_profiles,
+    )?;
+    let mut humble = jazzy;
+    humble.rosbag2_bagfile_information.version = 5;
+    let yaml = serde_yaml::to_string(&humble)?;
+    let value: serde_yaml::Value = serde_yaml::from_str(&yaml)?;
+    let info = &value["rosbag2_bagfile_information"];
+    assert!(info.get("files").is_some());
+    assert!(info.get("custom_data").is_none());
+    assert!(info.get("ros_distro").is_none());
+    let topic = &info["topics_with_message_count"][0]["topic_metadata"];
+    assert!(topic["offered_qos_profiles"].is_string());
+    assert!(topic.get("type_description_hash").is_none());
+
+    let profiles = topic["offered_qos_profiles"].as_str().unwrap_or_default();
+    assert!(profiles.contains("history: 1"));
+    assert!(profiles.contains("sec: 2147483647"));
+    assert!(!profiles.contains("keep_last"));
+
+    let mut reparsed: BagFileInfo = serde_yaml::from_str(&yaml)?;
+    let reparsed_profiles = &reparsed
+        .rosbag2_bagfile_information
+        .topics_with_message_count[0]
+        .topic_metadata
+        .offered_qos_profiles;
+    assert_eq!(QosProfile::parse_list(reparsed_profiles)?, jazzy_profiles);
+
+    // and back to version 9 with the policy names of Jazzy
+    reparsed.rosbag2_bagfile_information.version = 9;
+    let value: serde_yaml::Value = serde_yaml::from_str(&serde_yaml::to_string(&reparsed)?)?;
+    let profile = &value["rosbag2_bagfile_information"]["topics_with_message_count"][0]
+        ["topic_metadata"]["offered_qos_profiles"][0];
+    assert_eq!(profile["history"], "keep_last");
+    assert_eq!(profile["deadline"]["sec"], 9223372036i64);
+
+    Ok(())
+}
+
+#[test]
+fn test_read_version_9_bag() -> Result<()> {
+    let dir = tempdir()?;
+
+    let mut writer = Writer::new(dir.path());
+    writer.open()?;
+    let connection = writer.add_connection("/chatter", "std_msgs/msg/String", "cdr", "")?;
+    for i in 0..5 {
+        writer.write(&connection, i, &[i as u8])?;
+    }
+    writer.close()?;
+
+    let mut info: BagFileInfo = serde_yaml::from_str(&fs::read_to_string(&writer.metapath)?)?;
+    info.rosbag2_bagfile_information.version = 9;
+    info.rosbag2_bagfile_information.ros_distro = "jazzy".to_string();
+    fs::write(&writer.metapath, serde_yaml::to_string(&info)?)?;
+
+    let reader = Reader::new(dir.path())?;
+    assert_eq!(reader.ros_distro(), "jazzy");
+    assert_eq!(reader.messages(&ReadFilter::default())?.count(), 5);
+
+    Ok(())
+}
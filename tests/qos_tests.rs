@@ -0,0 +1,103 @@
+use anyhow::Result;
+use rosbag2_rs::{
+    DurabilityPolicy, Duration, HistoryPolicy, LivelinessPolicy, QosFormat, QosProfile, Reader,
+    ReliabilityPolicy, Rosbag2Error, Writer,
+};
+use tempfile::tempdir;
+
+const HUMBLE_LATCH: &str = r#"- history: 3
+  depth: 0
+  reliability: 1
+  durability: 1
+  deadline:
+    sec: 2147483647
+    nsec: 4294967295
+  lifespan:
+    sec: 2147483647
+    nsec: 4294967295
+  liveliness: 1
+  liveliness_lease_duration:
+    sec: 2147483647
+    nsec: 4294967295
+  avoid_ros_namespace_conventions: false
+"#;
+
+const JAZZY_LATCH: &str = r#"- history: unknown
+  depth: 0
+  reliability: reliable
+  durability: transient_local
+  deadline:
+    sec: 9223372036
+    nsec: 854775807
+  lifespan:
+    sec: 9223372036
+    nsec: 854775807
+  liveliness: automatic
+  liveliness_lease_duration:
+    sec: 9223372036
+    nsec: 854775807
+  avoid_ros_namespace_conventions: false
+"#;
+
+#[test]
+fn test_qos_formats() -> Result<()> {
+    // both formats parse to the same profile and are written back unchanged
+    for (yaml, format) in [
+        (HUMBLE_LATCH, QosFormat::Humble),
+        (JAZZY_LATCH, QosFormat::Jazzy),
+    ] {
+        let profiles = QosProfile::parse_list(yaml)?;
+        assert_eq!(profiles, vec![QosProfile::latched()]);
+        assert_eq!(QosProfile::format_list(&profiles, format)?, yaml);
+    }
+    assert_eq!(QosFormat::for_metadata_version(8), QosFormat::Humble);
+    assert_eq!(QosFormat::for_metadata_version(9), QosFormat::Jazzy);
+    assert_eq!(QosProfile::parse_list("")?, vec![]);
+
+    let profile = QosProfile {
+        history: HistoryPolicy::KeepAll,
+        reliability: ReliabilityPolicy::BestEffort,
+        durability: DurabilityPolicy::BestAvailable,
+        deadline: Some(Duration(1_500_000_000)),
+        liveliness: LivelinessPolicy::ManualByTopic,
+        ..Default::default()
+    };
+    let profiles = vec![profile];
+    let yaml = QosProfile::format_list(&profiles, QosFormat::Jazzy)?;
+    assert!(yaml.contains("durability: best_available"));
+    assert!(yaml.contains("sec: 1\n    nsec: 500000000"));
+    assert_eq!(QosProfile::parse_list(&yaml)?, profiles);
+
+    let invalid = HUMBLE_LATCH.replace("reliability: 1", "reliability: 7");
+    assert!(matches!(
+        QosProfile::parse_list(&invalid),
+        Err(Rosbag2Error::Metadata(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_write_qos_profiles() -> Result<()> {
+    let dir = tempdir()?;
+    let mut writer = Writer::new(dir.path());
+    writer.open()?;
+    let latched = writer.add_connection_with_qos(
+        "/tf_static",
+        "tf2_msgs/msg/TFMessage",
+        "cdr",
+        &[QosProfile::latched()],
+    )?;
+    assert_eq!(latched.ext.offered_qos_profiles, HUMBLE_LATCH);
+    writer.add_connection("/raw", "std_msgs/msg/String", "cdr", "")?;
+    writer.close()?;
+
+    let reader = Reader::new(dir.path())?;
+    assert_eq!(
+        reader.connections[0].qos_profiles()?,
+        vec![QosProfile::latched()]
+    );
+    assert_eq!(reader.connections[1].qos_profiles()?, vec![]);
+
+    Ok(())
+}
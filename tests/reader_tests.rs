@@ -1,5 +1,12 @@
 use anyhow::Result;
-use rosbag2_rs::{Reader, Writer};
+use rosbag2_rs::{
+    handle_messages, reindex, BagFileInfo, Duration, HandlerAction, Message, MessageId, ReadFilter,
+    Reader, Rosbag2Error, Sqlite3Reader, Time, Writer,
+};
+use rusqlite::Connection;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::{cell::RefCell, rc::Rc};
 use tempfile::tempdir;
 
@@ -37,17 +44,20 @@ fn test_messages_iterator_initialization() -> Result<()> {
     assert_eq!(reader.connections[0].msgtype, "msgtype1");
     assert_eq!(reader.connections[0].msgcount, 10);
 
-    assert_eq!(reader.duration(), 10);
+    assert_eq!(reader.duration(), Some(Duration(10)));
 
-    let msg_data: Vec<(i64, i64, Vec<u8>)> = vec![];
+    let msg_data: Vec<Message> = vec![];
     let msg_data = Rc::new(RefCell::new(msg_data)); // Wrap the vector in Rc and RefCell
 
     reader.handle_messages(
-        |(id, timestamp, data)| {
+        |message| {
             // Use `borrow_mut` to get a mutable reference to the vector
-            println!("processed message: {:?} {:?} {:?}", id, timestamp, data);
+            println!(
+                "processed message: {:?} {:?} {:?}",
+                message.connection.topic, message.timestamp, message.data
+            );
 
-            msg_data.borrow_mut().push((id, timestamp, data));
+            msg_data.borrow_mut().push(message);
             Ok(())
         },
         None,
@@ -55,6 +65,1129 @@ fn test_messages_iterator_initialization() -> Result<()> {
     )?;
 
     assert_eq!(msg_data.borrow().len(), 20);
+    let first = &msg_data.borrow()[0];
+    assert_eq!(first.connection.topic, "topic1");
+    assert_eq!(first.connection.msgtype, "msgtype1");
+    assert_eq!(first.timestamp, Time(0));
+    assert_eq!(first.publish_timestamp, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_messages_iterator() -> Result<()> {
+    let dir = tempdir().unwrap();
+
+    let mut writer = Writer::new(dir.path());
+    writer.open()?;
+    let connection = writer.add_connection("topic1", "msgtype1", "cdr", "")?;
+    // more messages than fit into a single page of the storage query
+    for i in 0..1000 {
+        writer.write(&connection, i as i64, &(i as u32).to_le_bytes())?;
+    }
+    writer.close()?;
+
+    let reader = Reader::new(dir.path())?;
+
+    let mut count = 0;
+    for (i, message) in reader.messages(&ReadFilter::default())?.enumerate() {
+        let message = message?;
+        assert_eq!(message.connection.id, connection.id);
+        assert_eq!(message.timestamp, Time(i as i64));
+        assert_eq!(message.data, (i as u32).to_le_bytes());
+        count += 1;
+    }
+    assert_eq!(count, 1000);
+
+    let filter = ReadFilter {
+        start: Some(Time(100)),
+        stop: Some(Time(600)),
+        ..Default::default()
+    };
+    let timestamps = reader
+        .messages(&filter)?
+        .map(|message| message.map(|m| m.timestamp.0))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(timestamps, (100..600).collect::<Vec<_>>());
+
+    let first_three = reader
+        .messages(&ReadFilter::default())?
+        .take(3)
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(first_three.len(), 3);
+    assert_eq!(first_three[2].timestamp, Time(2));
+
+    Ok(())
+}
+
+/// Writes a bag to `dir/name` with the given topics, each `(topic, timestamps)`.
+fn write_bag(dir: &Path, name: &str, topics: &[(&str, Vec<i64>)]) -> Result<BagFileInfo> {
+    let mut writer = Writer::new(dir.join(name));
+    writer.open()?;
+    for (topic, timestamps) in topics {
+        let connection = writer.add_connection(topic, "std_msgs/msg/Int64", "cdr", "")?;
+        for timestamp in timestamps {
+            writer.write(&connection, *timestamp, &timestamp.to_le_bytes())?;
+        }
+    }
+    writer.close()?;
+    Ok(serde_yaml::from_str(&fs::read_to_string(
+        &writer.metapath,
+    )?)?)
+}
+
+/// Writes every part with [`write_bag`] and moves their database files into the directory
+/// `split`, without metadata.yaml.
+fn write_split_bag(dir: &Path, parts: &[&[(&str, Vec<i64>)]]) -> Result<PathBuf> {
+    let bag_path = dir.join("split");
+    fs::create_dir(&bag_path)?;
+    for (index, topics) in parts.iter().enumerate() {
+        let name = format!("part_{index}");
+        write_bag(dir, &name, topics)?;
+        fs::rename(
+            dir.join(&name).join(format!("{name}.db3")),
+            bag_path.join(format!("{name}.db3")),
+        )?;
+    }
+    Ok(bag_path)
+}
+
+#[test]
+fn test_split_bag() -> Result<()> {
+    let dir = tempdir()?;
+
+    // the topics are registered in a different order in each file, so their ids differ
+    let first = write_bag(
+        dir.path(),
+        "part_0",
+        &[("/a", vec![0, 2, 4]), ("/b", vec![1, 3])],
+    )?;
+    let second = write_bag(
+        dir.path(),
+        "part_1",
+        &[("/b", vec![5, 7, 9]), ("/a", vec![6, 8])],
+    )?;
+
+    let bag_path = dir.path().join("split");
+    fs::create_dir(&bag_path)?;
+    fs::rename(
+        dir.path().join("part_0/part_0.db3"),
+        bag_path.join("part_0.db3"),
+    )?;
+    fs::rename(
+        dir.path().join("part_1/part_1.db3"),
+        bag_path.join("part_1.db3"),
+    )?;
+
+    let mut info = first;
+    let metadata = &mut info.rosbag2_bagfile_information;
+    let second = second.rosbag2_bagfile_information;
+    metadata.relative_file_paths = vec!["part_0.db3".to_string(), "part_1.db3".to_string()];
+    metadata.duration.nanoseconds = 9;
+    metadata.message_count = 10;
+    metadata.topics_with_message_count[0].message_count = 5;
+    metadata.topics_with_message_count[1].message_count = 5;
+    metadata.files.extend(second.files);
+    fs::write(
+        bag_path.join("metadata.yaml"),
+        serde_yaml::to_string(&info)?,
+    )?;
+
+    let reader = Reader::new(&bag_path)?;
+    let topic_a = reader.connections.iter().find(|c| c.topic == "/a").unwrap();
+
+    let messages = reader
+        .messages(&ReadFilter::default())?
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        messages.iter().map(|m| m.timestamp.0).collect::<Vec<_>>(),
+        (0..10).collect::<Vec<_>>()
+    );
+    for message in &messages {
+        let expected_a = [0, 2, 4, 6, 8].contains(&message.timestamp.0);
+        assert_eq!(message.connection.id == topic_a.id, expected_a);
+        assert_eq!(message.data, message.timestamp.0.to_le_bytes());
+    }
+
+    let filter = ReadFilter {
+        start: Some(Time(5)),
+        stop: Some(Time(8)),
+        ..Default::default()
+    };
+    let timestamps = reader
+        .messages(&filter)?
+        .map(|message| message.map(|m| m.timestamp.0))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(timestamps, vec![5, 6, 7]);
+
+    Ok(())
+}
+
+#[test]
+fn test_read_filter() -> Result<()> {
+    let dir = tempdir()?;
+
+    let mut writer = Writer::new(dir.path());
+    writer.open()?;
+    let imu = writer.add_connection("/imu", "sensor_msgs/msg/Imu", "cdr", "")?;
+    let quoted = writer.add_connection("/robot's/odom", "nav_msgs/msg/Odometry", "cdr", "")?;
+    let scan = writer.add_connection("/scan", "sensor_msgs/msg/LaserScan", "cdr", "")?;
+    for i in 0..30 {
+        let connection = [&imu, &quoted, &scan][i % 3];
+        writer.write(connection, i as i64, &[i as u8])?;
+    }
+    writer.close()?;
+
+    let reader = Reader::new(dir.path())?;
+    let read_ids = |filter: &ReadFilter| -> Result<Vec<i64>> {
+        Ok(reader
+            .messages(filter)?
+            .map(|message| message.map(|m| m.connection.id as i64))
+            .collect::<Result<_, _>>()?)
+    };
+
+    let filter = ReadFilter {
+        topics: ["/imu".to_string()].into(),
+        ..Default::default()
+    };
+    assert_eq!(read_ids(&filter)?, vec![imu.id as i64; 10]);
+
+    let filter = ReadFilter {
+        topics: ["/robot's/odom".to_string()].into(),
+        start: Some(Time(10)),
+        ..Default::default()
+    };
+    assert_eq!(read_ids(&filter)?, vec![quoted.id as i64; 7]);
+
+    let filter = ReadFilter {
+        msgtypes: [
+            "sensor_msgs/msg/Imu".to_string(),
+            "sensor_msgs/msg/LaserScan".to_string(),
+        ]
+        .into(),
+        connections: [scan.id].into(),
+        ..Default::default()
+    };
+    assert_eq!(read_ids(&filter)?, vec![scan.id as i64; 10]);
+
+    let filter = ReadFilter {
+        topics: ["/does_not_exist".to_string()].into(),
+        ..Default::default()
+    };
+    assert!(read_ids(&filter)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_open_bag_without_metadata_yaml() -> Result<()> {
+    let dir = tempdir()?;
+
+    let mut writer = Writer::new(dir.path());
+    writer.open()?;
+    let imu = writer.add_connection("/imu", "sensor_msgs/msg/Imu", "cdr", "")?;
+    let odom = writer.add_connection("/odom", "nav_msgs/msg/Odometry", "cdr", "")?;
+    for i in 0..10 {
+        writer.write(if i < 4 { &imu } else { &odom }, 100 + i, &[i as u8])?;
+    }
+    writer.close()?;
+    fs::remove_file(&writer.metapath)?;
+
+    // the metadata written to the database on close is used
+    let reader = Reader::new(dir.path())?;
+    assert_eq!(reader.message_count(), 10);
+    assert_eq!(reader.connections.len(), 2);
+    assert_eq!(reader.messages(&ReadFilter::default())?.count(), 10);
+
+    // without it, as after a crash, the metadata is reconstructed from the messages
+    Connection::open(&writer.dbpath)?.execute("DELETE FROM metadata", [])?;
+    let reader = Reader::new(dir.path())?;
+    assert_eq!(reader.message_count(), 10);
+    assert_eq!(reader.start_time(), Some(Time(100)));
+    assert_eq!(reader.end_time(), Some(Time(110)));
+    assert_eq!(reader.duration(), Some(Duration(10)));
+    let topics = reader.topics();
+    assert_eq!(topics["/imu"].msgcount, 4);
+    assert_eq!(topics["/odom"].msgcount, 6);
+    assert_eq!(
+        topics["/odom"].msgtype.as_deref(),
+        Some("nav_msgs/msg/Odometry")
+    );
+    assert_eq!(reader.messages(&ReadFilter::default())?.count(), 10);
+
+    Ok(())
+}
+
+#[test]
+fn test_reader_errors() -> Result<()> {
+    let dir = tempdir()?;
+    assert!(matches!(
+        Reader::new(dir.path()),
+        Err(Rosbag2Error::MissingMetadata(_))
+    ));
+
+    fs::write(
+        dir.path().join("metadata.yaml"),
+        "rosbag2_bagfile_information: [",
+    )?;
+    assert!(matches!(
+        Reader::new(dir.path()),
+        Err(Rosbag2Error::Metadata(_))
+    ));
+
+    let bag = tempdir()?;
+    let mut writer = Writer::new(bag.path());
+    writer.open()?;
+    let connection = writer.add_connection("/a", "std_msgs/msg/Int64", "cdr", "")?;
+    writer.write(&connection, 0, &[0])?;
+    writer.close()?;
+    let mut info: BagFileInfo = serde_yaml::from_str(&fs::read_to_string(&writer.metapath)?)?;
+    info.rosbag2_bagfile_information.version = 42;
+    fs::write(&writer.metapath, serde_yaml::to_string(&info)?)?;
+    assert!(matches!(
+        Reader::new(bag.path()),
+        Err(Rosbag2Error::UnsupportedVersion(42))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_handle_messages_control() -> Result<()> {
+    let dir = tempdir()?;
+
+    let mut writer = Writer::new(dir.path());
+    writer.open()?;
+    let connection = writer.add_connection("topic1", "msgtype1", "cdr", "")?;
+    for i in 0..10 {
+        writer.write(&connection, i, &[i as u8])?;
+    }
+    writer.close()?;
+
+    let mut reader = Reader::new(dir.path())?;
+
+    // stopping early, with mutable state in the handler
+    let mut seen = vec![];
+    reader.handle_messages(
+        |message| {
+            seen.push(message.timestamp.0);
+            Ok(if seen.len() == 3 {
+                HandlerAction::Stop
+            } else {
+                HandlerAction::Continue
+            })
+        },
+        None,
+        None,
+    )?;
+    assert_eq!(seen, vec![0, 1, 2]);
+
+    // handler errors are returned to the caller
+    let result = reader.handle_messages(
+        |message| {
+            if message.timestamp == Time(4) {
+                return Err(Rosbag2Error::Handler("bad message".into()));
+            }
+            Ok(())
+        },
+        None,
+        None,
+    );
+    assert!(matches!(result, Err(Rosbag2Error::Handler(_))));
+
+    // the same holds for the statement based API of the storage
+    let mut storage = Sqlite3Reader::new(vec![writer.dbpath.to_string_lossy().into_owned()]);
+    storage.open()?;
+    let mut count = 0;
+    handle_messages(
+        storage.messages_statement(&reader.connections, Some(5), None)?,
+        |_| {
+            count += 1;
+            Ok(HandlerAction::Stop)
+        },
+    )?;
+    assert_eq!(count, 1);
+    let result = handle_messages(
+        storage.messages_statement(&reader.connections, None, None)?,
+        |_| Err::<(), _>(Rosbag2Error::Handler("bad message".into())),
+    );
+    assert!(matches!(result, Err(Rosbag2Error::Handler(_))));
+
+    Ok(())
+}
+
+#[test]
+fn test_connection_ids_from_database() -> Result<()> {
+    let dir = tempdir()?;
+
+    let mut writer = Writer::new(dir.path());
+    writer.open()?;
+    let imu = writer.add_connection("/imu", "sensor_msgs/msg/Imu", "cdr", "")?;
+    let odom = writer.add_connection("/odom", "nav_msgs/msg/Odometry", "cdr", "")?;
+    for i in 0..6 {
+        writer.write(if i % 3 == 0 { &imu } else { &odom }, i, &[i as u8])?;
+    }
+    writer.close()?;
+
+    // renumber the topics as a merge tool might, without touching metadata.yaml, and
+    // move a message to a second connection of /imu with other QoS profiles
+    let conn = Connection::open(&writer.dbpath)?;
+    conn.execute_batch(
+        "UPDATE topics SET id = id + 10; UPDATE messages SET topic_id = topic_id + 10;
+         INSERT INTO topics (id, name, type, serialization_format, offered_qos_profiles)
+         SELECT 13, name, type, serialization_format, 'other' FROM topics WHERE id = 11;
+         UPDATE messages SET topic_id = 13 WHERE timestamp = 3;",
+    )?;
+    drop(conn);
+
+    let reader = Reader::new(dir.path())?;
+    let ids = reader
+        .connections
+        .iter()
+        .map(|c| (c.topic.as_str(), c.id, c.msgcount))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ids,
+        vec![("/imu", 11, 1), ("/odom", 12, 4), ("/imu", 13, 1)]
+    );
+    assert_eq!(reader.connections[2].ext.offered_qos_profiles, "other");
+
+    for message in reader.messages(&ReadFilter::default())? {
+        let message = message?;
+        let expected = match message.timestamp.0 {
+            0 => 11,
+            3 => 13,
+            _ => 12,
+        };
+        assert_eq!(message.connection.id, expected);
+    }
+
+    let filter = ReadFilter {
+        connections: [13].into(),
+        ..Default::default()
+    };
+    let messages = reader.messages(&filter)?.collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].timestamp, Time(3));
+    assert_eq!(messages[0].connection.ext.offered_qos_profiles, "other");
+
+    Ok(())
+}
+
+#[test]
+fn test_message_counts() -> Result<()> {
+    let dir = tempdir()?;
+
+    let mut writer = Writer::new(dir.path());
+    writer.open()?;
+    let imu = writer.add_connection("/imu", "sensor_msgs/msg/Imu", "cdr", "")?;
+    let odom = writer.add_connection("/odom", "nav_msgs/msg/Odometry", "cdr", "")?;
+    for i in 0..3 {
+        writer.write(if i == 0 { &odom } else { &imu }, i, &[i as u8])?;
+    }
+    writer.close()?;
+
+    // the counts of a metadata.yaml listing every connection are used as they are
+    let mut info: BagFileInfo = serde_yaml::from_str(&fs::read_to_string(&writer.metapath)?)?;
+    let topics = &mut info.rosbag2_bagfile_information.topics_with_message_count;
+    topics[0].message_count = 7;
+    topics[1].message_count = 8;
+    fs::write(&writer.metapath, serde_yaml::to_string(&info)?)?;
+    let counts = |reader: &Reader| {
+        reader
+            .connections
+            .iter()
+            .map(|c| (c.id, c.msgcount))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(counts(&Reader::new(dir.path())?), vec![(1, 7), (2, 8)]);
+
+    // otherwise the messages are counted in the database
+    info.rosbag2_bagfile_information
+        .topics_with_message_count
+        .truncate(1);
+    fs::write(&writer.metapath, serde_yaml::to_string(&info)?)?;
+    assert_eq!(counts(&Reader::new(dir.path())?), vec![(1, 2), (2, 1)]);
+
+    Ok(())
+}
+
+#[test]
+fn test_read_file_compressed_bag() -> Result<()> {
+    let dir = tempdir()?;
+
+    let mut writer = Writer::new(dir.path());
+    writer.open()?;
+    let connection = writer.add_connection("/camera", "sensor_msgs/msg/Image", "cdr", "")?;
+    for i in 0..20 {
+        writer.write(&connection, i, &[i as u8; 64])?;
+    }
+    writer.close()?;
+
+    // compress the database like `ros2 bag record --compression-mode file` does
+    let compressed_name = format!(
+        "{}.zstd",
+        writer.dbpath.file_name().unwrap().to_string_lossy()
+    );
+    let db = fs::read(&writer.dbpath)?;
+    fs::write(
+        dir.path().join(&compressed_name),
+        zstd::encode_all(&db[..], 0)?,
+    )?;
+    fs::remove_file(&writer.dbpath)?;
+
+    let mut info: BagFileInfo = serde_yaml::from_str(&fs::read_to_string(&writer.metapath)?)?;
+    let metadata = &mut info.rosbag2_bagfile_information;
+    metadata.compression_mode = "FILE".to_string();
+    metadata.compression_format = "zstd".to_string();
+    metadata.relative_file_paths = vec![compressed_name.clone()];
+    metadata.files[0].path = compressed_name.clone();
+    fs::write(&writer.metapath, serde_yaml::to_string(&info)?)?;
+
+    let reader = Reader::new(dir.path())?;
+    assert_eq!(reader.compression_mode(), Some("file".to_string()));
+    assert_eq!(reader.compression_format(), "zstd");
+    let messages = reader
+        .messages(&ReadFilter::default())?
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(messages.len(), 20);
+    assert_eq!(messages[7].data, vec![7u8; 64]);
+
+    let files = vec![fs::read(dir.path().join(&compressed_name))?];
+    let reader = Reader::from_bytes(&fs::read_to_string(&writer.metapath)?, files)?;
+    let from_bytes = reader
+        .messages(&ReadFilter::default())?
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(from_bytes, messages);
+
+    // other formats are rejected
+    info.rosbag2_bagfile_information.compression_format = "lz4".to_string();
+    fs::write(&writer.metapath, serde_yaml::to_string(&info)?)?;
+    assert!(matches!(
+        Reader::new(dir.path()),
+        Err(Rosbag2Error::UnsupportedCompression(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_read_message_compressed_bag() -> Result<()> {
+    let dir = tempdir()?;
+
+    // store every message compressed on its own, like `--compression-mode message`
+    let mut writer = Writer::new(dir.path());
+    writer.open()?;
+    let connection = writer.add_connection("/points", "sensor_msgs/msg/PointCloud2", "cdr", "")?;
+    for i in 0..10 {
+        writer.write(&connection, i, &zstd::encode_all(&[i as u8; 256][..], 0)?)?;
+    }
+    writer.close()?;
+
+    let mut info: BagFileInfo = serde_yaml::from_str(&fs::read_to_string(&writer.metapath)?)?;
+    info.rosbag2_bagfile_information.compression_mode = "MESSAGE".to_string();
+    info.rosbag2_bagfile_information.compression_format = "zstd".to_string();
+    fs::write(&writer.metapath, serde_yaml::to_string(&info)?)?;
+
+    let mut reader = Reader::new(dir.path())?;
+    assert_eq!(reader.compression_mode(), Some("message".to_string()));
+
+    for message in reader.messages(&ReadFilter::default())? {
+        let message = message?;
+        assert_eq!(message.data, vec![message.timestamp.0 as u8; 256]);
+    }
+
+    let raw = ReadFilter {
+        raw: true,
+        ..Default::default()
+    };
+    for message in reader.messages(&raw)? {
+        let message = message?;
+        assert_eq!(
+            zstd::decode_all(&message.data[..])?,
+            vec![message.timestamp.0 as u8; 256]
+        );
+    }
+
+    let mut sizes = vec![];
+    reader.handle_messages(
+        |message| {
+            sizes.push(message.data.len());
+            Ok(())
+        },
+        None,
+        None,
+    )?;
+    assert_eq!(sizes, vec![256; 10]);
+
+    reader.for_each_message(&ReadFilter::default(), |message| {
+        assert_eq!(message.data, [message.timestamp.0 as u8; 256]);
+        Ok(())
+    })?;
+
+    reader.scan_messages(&ReadFilter::default(), |info| {
+        assert!(info.size < 256);
+        assert_eq!(
+            reader.load_payload(info.id)?,
+            vec![info.timestamp.0 as u8; 256]
+        );
+        Ok(())
+    })?;
+
+    // reindexing keeps the compression, which the database files do not record
+    let metadata = reindex(dir.path())?;
+    assert_eq!(metadata.compression_mode, "MESSAGE");
+    assert_eq!(metadata.compression_format, "zstd");
+    let reader = Reader::new(dir.path())?;
+    assert_eq!(reader.compression_mode(), Some("message".to_string()));
+
+    info.rosbag2_bagfile_information.compression_mode = "FILE".to_string();
+    fs::write(&writer.metapath, serde_yaml::to_string(&info)?)?;
+    assert!(matches!(
+        reindex(dir.path()),
+        Err(Rosbag2Error::UnsupportedCompression(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_cursor() -> Result<()> {
+    let dir = tempdir()?;
+
+    // a split bag whose files share the timestamp 4
+    let bag_path = write_split_bag(
+        dir.path(),
+        &[&[("/a", vec![0, 2, 4])], &[("/b", vec![3, 4, 5])]],
+    )?;
+
+    let reader = Reader::new(&bag_path)?;
+    let a = reader
+        .connections
+        .iter()
+        .find(|c| c.topic == "/a")
+        .unwrap()
+        .id as i64;
+    let b = reader
+        .connections
+        .iter()
+        .find(|c| c.topic == "/b")
+        .unwrap()
+        .id as i64;
+    let step = |message: Option<rosbag2_rs::Result<Message>>| {
+        message
+            .transpose()
+            .map(|m| m.map(|m| (m.connection.id as i64, m.timestamp.0)))
+    };
+
+    // walking forward yields the same messages as the iterator
+    let mut cursor = reader.cursor(&ReadFilter::default())?;
+    assert_eq!(cursor.position(), None);
+    let forward = cursor.by_ref().collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        forward,
+        reader
+            .messages(&ReadFilter::default())?
+            .collect::<Result<Vec<_>, _>>()?
+    );
+    assert_eq!(cursor.position(), Some(Time(5)));
+    assert_eq!(step(cursor.prev())?, Some((b, 5)));
+
+    // prev after next returns the same message, also between files
+    cursor.seek(4);
+    assert_eq!(step(cursor.next())?, Some((a, 4)));
+    assert_eq!(step(cursor.next())?, Some((b, 4)));
+    assert_eq!(step(cursor.prev())?, Some((b, 4)));
+    assert_eq!(step(cursor.prev())?, Some((a, 4)));
+    assert_eq!(step(cursor.prev())?, Some((b, 3)));
+    assert_eq!(cursor.position(), Some(Time(3)));
+    assert_eq!(step(cursor.prev())?, Some((a, 2)));
+    assert_eq!(step(cursor.prev())?, Some((a, 0)));
+    assert_eq!(step(cursor.prev())?, None);
+    assert_eq!(step(cursor.next())?, Some((a, 0)));
+
+    cursor.seek(6);
+    assert_eq!(step(cursor.next())?, None);
+    assert_eq!(step(cursor.prev())?, Some((b, 5)));
+
+    // the filter applies to the cursor as well
+    let filter = ReadFilter {
+        topics: ["/a".to_string()].into(),
+        stop: Some(Time(4)),
+        ..Default::default()
+    };
+    let mut cursor = reader.cursor(&filter)?;
+    cursor.seek(3);
+    assert_eq!(step(cursor.next())?, None);
+    assert_eq!(step(cursor.prev())?, Some((a, 2)));
+
+    Ok(())
+}
+
+#[test]
+fn test_reverse_messages() -> Result<()> {
+    let dir = tempdir()?;
+
+    // a split bag whose files share the timestamp 4
+    let bag_path = write_split_bag(
+        dir.path(),
+        &[
+            &[("/a", vec![0, 2, 4]), ("/c", vec![1])],
+            &[("/b", vec![3, 4, 5]), ("/a", vec![6])],
+        ],
+    )?;
+    let reader = Reader::new(&bag_path)?;
+
+    // the reverse stream is exactly the forward stream backwards
+    let filters = [
+        ReadFilter::default(),
+        ReadFilter {
+            topics: ["/a".to_string(), "/b".to_string()].into(),
+            start: Some(Time(2)),
+            stop: Some(Time(6)),
+            ..Default::default()
+        },
+    ];
+    for filter in filters {
+        let mut forward = reader.messages(&filter)?.collect::<Result<Vec<_>, _>>()?;
+        let reverse = reader
+            .messages(&ReadFilter {
+                reverse: true,
+                ..filter
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        assert!(!forward.is_empty());
+        forward.reverse();
+        assert_eq!(reverse, forward);
+    }
+
+    // the latest messages come first
+    let last = reader
+        .messages(&ReadFilter {
+            reverse: true,
+            ..Default::default()
+        })?
+        .take(2)
+        .map(|message| message.map(|m| m.timestamp.0))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(last, vec![6, 5]);
+
+    Ok(())
+}
+
+#[test]
+fn test_for_each_message() -> Result<()> {
+    let dir = tempdir()?;
+    let bag_path = write_split_bag(
+        dir.path(),
+        &[
+            &[("/a", vec![0, 2, 4]), ("/c", vec![1])],
+            &[("/b", vec![3, 4, 5]), ("/a", vec![6])],
+        ],
+    )?;
+    let reader = Reader::new(&bag_path)?;
+
+    // the borrowed data arrives in the order of the iterator, in both directions
+    let filters = [
+        ReadFilter::default(),
+        ReadFilter {
+            topics: ["/a".to_string(), "/b".to_string()].into(),
+            start: Some(Time(2)),
+            stop: Some(Time(6)),
+            reverse: true,
+            ..Default::default()
+        },
+    ];
+    for filter in filters {
+        let mut messages = vec![];
+        reader.for_each_message(&filter, |message| {
+            messages.push(Message {
+                connection: message.connection.clone().into(),
+                timestamp: message.timestamp,
+                publish_timestamp: message.publish_timestamp,
+                data: message.data.to_vec(),
+            });
+            Ok(())
+        })?;
+        assert_eq!(
+            messages,
+            reader.messages(&filter)?.collect::<Result<Vec<_>, _>>()?
+        );
+    }
+
+    let mut timestamps = vec![];
+    reader.for_each_message(&ReadFilter::default(), |message| {
+        timestamps.push(message.timestamp.0);
+        Ok(if timestamps.len() == 3 {
+            HandlerAction::Stop
+        } else {
+            HandlerAction::Continue
+        })
+    })?;
+    assert_eq!(timestamps, vec![0, 1, 2]);
+
+    Ok(())
+}
+
+#[test]
+fn test_scan_messages() -> Result<()> {
+    let dir = tempdir()?;
+
+    let mut writer = Writer::new(dir.path());
+    writer.open()?;
+    let imu = writer.add_connection("/imu", "sensor_msgs/msg/Imu", "cdr", "")?;
+    let points = writer.add_connection("/points", "sensor_msgs/msg/PointCloud2", "cdr", "")?;
+    for i in 0..4 {
+        writer.write(&imu, i * 10, &[i as u8; 8])?;
+        writer.write(&points, i * 10 + 5, &vec![i as u8; 100_000])?;
+    }
+    writer.close()?;
+    let reader = Reader::new(dir.path())?;
+
+    let mut infos = vec![];
+    reader.scan_messages(&ReadFilter::default(), |info| {
+        infos.push(info);
+        Ok(())
+    })?;
+    let summary = infos
+        .iter()
+        .map(|info| (info.connection.id, info.timestamp.0, info.size))
+        .collect::<Vec<_>>();
+    let (imu, points) = (imu.id, points.id);
+    assert_eq!(
+        summary,
+        vec![
+            (imu, 0, 8),
+            (points, 5, 100_000),
+            (imu, 10, 8),
+            (points, 15, 100_000),
+            (imu, 20, 8),
+            (points, 25, 100_000),
+            (imu, 30, 8),
+            (points, 35, 100_000),
+        ]
+    );
+
+    // the data of a single message is loaded or streamed on demand
+    let info = &infos[5];
+    assert_eq!(info.connection.topic, "/points");
+    assert_eq!(reader.load_payload(info.id)?, vec![2; 100_000]);
+    let mut head = [0; 16];
+    reader.open_payload(info.id)?.read_exact(&mut head)?;
+    assert_eq!(head, [2; 16]);
+
+    let missing = MessageId {
+        file: 0,
+        row: i64::MAX,
+    };
+    assert!(matches!(
+        reader.load_payload(missing),
+        Err(Rosbag2Error::Sqlite(_))
+    ));
+    assert!(matches!(
+        reader.load_payload(MessageId { file: 1, row: 1 }),
+        Err(Rosbag2Error::UnknownFile(1))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_reindex() -> Result<()> {
+    let dir = tempdir()?;
+    let bag_path = write_split_bag(
+        dir.path(),
+        &[
+            &[("/a", vec![0, 2, 4]), ("/c", vec![1])],
+            &[("/b", vec![3, 4, 5]), ("/a", vec![6])],
+        ],
+    )?;
+
+    // a stale metadata.yaml that only knows the first file
+    let mut stale = write_bag(dir.path(), "stale", &[("/a", vec![0, 2, 4])])?;
+    let stale_metadata = &mut stale.rosbag2_bagfile_information;
+    stale_metadata.version = 6;
+    stale_metadata
+        .custom_data
+        .insert("recorded_by".to_string(), "test".to_string());
+    fs::write(
+        bag_path.join("metadata.yaml"),
+        serde_yaml::to_string(&stale)?,
+    )?;
+
+    let metadata = reindex(&bag_path)?;
+    assert_eq!(
+        metadata.relative_file_paths,
+        vec!["part_0.db3", "part_1.db3"]
+    );
+    assert_eq!(metadata.message_count, 8);
+    assert_eq!(metadata.starting_time.nanoseconds_since_epoch, 0);
+    assert_eq!(metadata.duration.nanoseconds, 6);
+    let file_counts = metadata
+        .files
+        .iter()
+        .map(|file| (file.path.as_str(), file.message_count))
+        .collect::<Vec<_>>();
+    assert_eq!(file_counts, vec![("part_0.db3", 4), ("part_1.db3", 4)]);
+    assert_eq!(metadata.custom_data["recorded_by"], "test");
+
+    // the rewritten metadata.yaml describes the whole bag
+    let reader = Reader::new(&bag_path)?;
+    assert_eq!(reader.metadata.custom_data["recorded_by"], "test");
+    let topics = reader.topics();
+    assert_eq!(topics["/a"].msgcount, 4);
+    assert_eq!(topics["/b"].msgcount, 3);
+    assert_eq!(topics["/c"].msgcount, 1);
+    assert_eq!(reader.messages(&ReadFilter::default())?.count(), 8);
+
+    Ok(())
+}
+
+#[test]
+fn test_open_file() -> Result<()> {
+    let dir = tempdir()?;
+    write_bag(
+        dir.path(),
+        "bag",
+        &[("/imu", vec![100, 102]), ("/odom", vec![101, 110])],
+    )?;
+    let file = dir.path().join("bag").join("bag.db3");
+
+    // the file of a bag directory uses the metadata stored in it
+    let reader = Reader::new(&file)?;
+    assert_eq!(reader.metadata.relative_file_paths, vec!["bag.db3"]);
+    assert_eq!(reader.message_count(), 4);
+    assert_eq!(reader.messages(&ReadFilter::default())?.count(), 4);
+
+    // a renamed copy on its own is described by its topics and messages tables
+    let copy = dir.path().join("from_a_colleague.db3");
+    fs::copy(&file, &copy)?;
+    let reader = Reader::open_file(&copy)?;
+    assert_eq!(
+        reader.metadata.relative_file_paths,
+        vec!["from_a_colleague.db3"]
+    );
+    assert_eq!(reader.connections.len(), 2);
+    assert_eq!(reader.connections[1].msgcount, 2);
+    assert_eq!(reader.start_time(), Some(Time(100)));
+    assert_eq!(reader.end_time(), Some(Time(111)));
+    let timestamps = reader
+        .messages(&ReadFilter::default())?
+        .map(|message| message.map(|m| m.timestamp.0))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(timestamps, vec![100, 101, 102, 110]);
+
+    assert!(matches!(
+        Reader::open_file(dir.path().join("missing.db3")),
+        Err(Rosbag2Error::Io(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_statistics() -> Result<()> {
+    let dir = tempdir()?;
+    let bag_path = write_split_bag(
+        dir.path(),
+        &[
+            &[("/imu", vec![0, 100, 200]), ("/odom", vec![50])],
+            &[("/imu", vec![300, 450])],
+        ],
+    )?;
+    let reader = Reader::new(&bag_path)?;
+
+    let statistics = reader.statistics(&ReadFilter::default())?;
+    assert_eq!(statistics.message_count, 6);
+    assert_eq!(statistics.bytes, 48);
+    assert_eq!(statistics.first, Some(Time(0)));
+    assert_eq!(statistics.last, Some(Time(450)));
+    assert_eq!(statistics.topics.len(), 2);
+
+    // the gap between the files counts like any other
+    let imu = &statistics.topics[0];
+    assert_eq!((imu.topic.as_str(), imu.message_count), ("/imu", 5));
+    assert_eq!((imu.bytes, imu.min_size, imu.max_size), (40, 8, 8));
+    assert_eq!((imu.mean_size, imu.size_stddev), (8.0, 0.0));
+    assert_eq!((imu.first, imu.last), (Time(0), Time(450)));
+    assert_eq!(imu.min_gap, Some(Duration(100)));
+    assert_eq!(imu.max_gap, Some(Duration(150)));
+    assert_eq!(imu.mean_gap, Some(Duration(113)));
+    // gaps of 100, 100, 100 and 150 deviate by 12.5 and 37.5 from their mean
+    assert_eq!(imu.jitter, Some(Duration(22)));
+    assert_eq!(imu.frequency, Some(4.0 / 450e-9));
+
+    let odom = &statistics.topics[1];
+    assert_eq!((odom.topic.as_str(), odom.message_count), ("/odom", 1));
+    assert_eq!(
+        (odom.frequency, odom.mean_gap, odom.jitter),
+        (None, None, None)
+    );
+
+    let filter = ReadFilter {
+        topics: ["/imu".to_string()].into(),
+        start: Some(Time(100)),
+        stop: Some(Time(400)),
+        ..Default::default()
+    };
+    let statistics = reader.statistics(&filter)?;
+    assert_eq!(statistics.message_count, 3);
+    assert_eq!(statistics.topics[0].max_gap, Some(Duration(100)));
+    assert_eq!(statistics.topics[0].jitter, Some(Duration(0)));
+
+    let filter = ReadFilter {
+        start: Some(Time(1000)),
+        ..Default::default()
+    };
+    assert_eq!(reader.statistics(&filter)?, Default::default());
+
+    // the connections of a topic are aggregated together, gaps span both of them
+    let dir = tempdir()?;
+    let mut writer = Writer::new(dir.path());
+    writer.open()?;
+    let imu = writer.add_connection("/imu", "sensor_msgs/msg/Imu", "cdr", "")?;
+    for i in 0..4 {
+        writer.write(&imu, i * 100, &[i as u8])?;
+    }
+    writer.close()?;
+    let conn = Connection::open(&writer.dbpath)?;
+    conn.execute_batch(
+        "INSERT INTO topics (id, name, type, serialization_format, offered_qos_profiles)
+         SELECT 2, name, type, serialization_format, 'other' FROM topics WHERE id = 1;
+         UPDATE messages SET topic_id = 2 WHERE timestamp = 100;",
+    )?;
+    drop(conn);
+
+    let reader = Reader::new(dir.path())?;
+    let statistics = reader.statistics(&ReadFilter::default())?;
+    assert_eq!(statistics.topics.len(), 1);
+    let imu = &statistics.topics[0];
+    assert_eq!(imu.connections, vec![1, 2]);
+    assert_eq!(imu.msgtype.as_deref(), Some("sensor_msgs/msg/Imu"));
+    assert_eq!(imu.message_count, 4);
+    assert_eq!(
+        (imu.min_gap, imu.max_gap),
+        (Some(Duration(100)), Some(Duration(100)))
+    );
+
+    // statistics of a single connection are those of a filter selecting it
+    let filter = ReadFilter {
+        connections: [1].into(),
+        ..Default::default()
+    };
+    let statistics = reader.statistics(&filter)?;
+    assert_eq!(statistics.topics[0].connections, vec![1]);
+    assert_eq!(statistics.topics[0].message_count, 3);
+    assert_eq!(statistics.topics[0].max_gap, Some(Duration(200)));
+
+    Ok(())
+}
+
+#[test]
+fn test_from_bytes() -> Result<()> {
+    let dir = tempdir()?;
+    let bag_path = write_split_bag(
+        dir.path(),
+        &[
+            &[("/imu", vec![0, 2, 4]), ("/odom", vec![1])],
+            &[("/odom", vec![3, 5])],
+        ],
+    )?;
+    // files recorded with a write-ahead log are read as well
+    Connection::open(bag_path.join("part_1.db3"))?.pragma_update(None, "journal_mode", "WAL")?;
+    let metadata = reindex(&bag_path)?;
+
+    let files = metadata
+        .relative_file_paths
+        .iter()
+        .map(|name| fs::read(bag_path.join(name)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let metadata_yaml = fs::read_to_string(bag_path.join("metadata.yaml"))?;
+    let reader = Reader::from_bytes(&metadata_yaml, files.clone())?;
+    let expected = Reader::new(&bag_path)?;
+    assert_eq!(reader.connections, expected.connections);
+
+    let filter = ReadFilter {
+        topics: ["/odom".to_string()].into(),
+        start: Some(Time(2)),
+        ..Default::default()
+    };
+    let messages = reader.messages(&filter)?.collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        messages,
+        expected.messages(&filter)?.collect::<Result<Vec<_>, _>>()?
+    );
+    assert_eq!(
+        messages.iter().map(|m| m.timestamp.0).collect::<Vec<_>>(),
+        vec![3, 5]
+    );
+    assert_eq!(reader.statistics(&ReadFilter::default())?.message_count, 6);
+
+    // a clone copies the in-memory databases
+    let clone = reader.try_clone()?;
+    drop(reader);
+    assert_eq!(
+        clone.messages(&filter)?.collect::<Result<Vec<_>, _>>()?,
+        messages
+    );
+
+    assert!(matches!(
+        Reader::from_bytes(&metadata_yaml, files[..1].to_vec()),
+        Err(Rosbag2Error::FileCount {
+            expected: 2,
+            found: 1
+        })
+    ));
+    assert!(matches!(
+        Reader::from_bytes(&metadata_yaml, vec![vec![0; 16], files[1].clone()]),
+        Err(Rosbag2Error::Sqlite(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_topics() -> Result<()> {
+    let dir = tempdir()?;
+    let mut writer = Writer::new(dir.path());
+    writer.open()?;
+    let old = writer.add_connection("/a", "pkg/msg/Old", "cdr", "")?;
+    let b = writer.add_connection("/b", "std_msgs/msg/Int64", "cdr", "")?;
+    let new = writer.add_connection("/a", "pkg/msg/New", "cdr", "")?;
+    writer.write(&old, 0, &[0])?;
+    writer.write(&b, 1, &[1])?;
+    writer.write(&new, 2, &[2])?;
+    writer.write(&new, 3, &[3])?;
+    writer.close()?;
+
+    // a second connection of /b with the same type and other QoS profiles
+    let conn = Connection::open(&writer.dbpath)?;
+    conn.execute_batch(
+        "INSERT INTO topics (id, name, type, serialization_format, offered_qos_profiles)
+         SELECT 4, name, type, serialization_format, 'other' FROM topics WHERE name = '/b';
+         INSERT INTO messages (topic_id, timestamp, data) VALUES (4, 4, x'04'), (4, 5, x'05');",
+    )?;
+    drop(conn);
+
+    // every connection of a topic is kept, and differing types are a conflict
+    let topics = Reader::new(dir.path())?.topics();
+    assert_eq!(topics.len(), 2);
+    assert_eq!(topics["/a"].msgcount, 3);
+    assert_eq!(topics["/a"].msgtype, None);
+    let ids = topics["/a"]
+        .connections
+        .iter()
+        .map(|c| (c.id, c.msgcount))
+        .collect::<Vec<_>>();
+    assert_eq!(ids, vec![(old.id, 1), (new.id, 2)]);
+    assert_eq!(topics["/b"].msgcount, 3);
+    assert_eq!(topics["/b"].msgtype.as_deref(), Some("std_msgs/msg/Int64"));
+    let qos = topics["/b"]
+        .connections
+        .iter()
+        .map(|c| (c.id, c.msgcount, c.ext.offered_qos_profiles.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(qos, vec![(b.id, 1, ""), (4, 2, "other")]);
 
     Ok(())
 }
@@ -0,0 +1,104 @@
+use anyhow::Result;
+use rosbag2_rs::{BagFileInfo, Duration, DurationMsg, Reader, Time, TimeMsg, Writer};
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tempfile::tempdir;
+
+#[test]
+fn test_time_conversions() -> Result<()> {
+    let time = Time(1_700_000_000_123_456_789);
+    let msg = TimeMsg::try_from(time)?;
+    assert_eq!(
+        msg,
+        TimeMsg {
+            sec: 1_700_000_000,
+            nanosec: 123_456_789
+        }
+    );
+    assert_eq!(Time::from(msg), time);
+    // the seconds of builtin_interfaces overflow in 2038
+    assert!(TimeMsg::try_from(Time(i64::MAX)).is_err());
+
+    // nanoseconds stay positive before the epoch and for negative durations
+    let msg = DurationMsg::try_from(Duration(-1_500_000_000))?;
+    assert_eq!(
+        msg,
+        DurationMsg {
+            sec: -2,
+            nanosec: 500_000_000
+        }
+    );
+    assert_eq!(Duration::from(msg), Duration(-1_500_000_000));
+
+    let system = SystemTime::from(time);
+    assert_eq!(
+        system.duration_since(UNIX_EPOCH)?,
+        std::time::Duration::new(1_700_000_000, 123_456_789)
+    );
+    assert_eq!(Time::try_from(system)?, time);
+    assert_eq!(Time::try_from(SystemTime::from(Time(-5)))?, Time(-5));
+
+    assert_eq!(
+        std::time::Duration::try_from(Duration(1_500))?,
+        std::time::Duration::from_nanos(1_500)
+    );
+    assert!(std::time::Duration::try_from(Duration(-1)).is_err());
+    assert_eq!(Time(10) + Duration(5) - Time(3), Duration(12));
+
+    Ok(())
+}
+
+#[cfg(feature = "chrono")]
+#[test]
+fn test_chrono_conversions() -> Result<()> {
+    use chrono::{DateTime, TimeDelta, Utc};
+
+    let time = Time(-1_500_000_000);
+    let datetime = DateTime::<Utc>::from(time);
+    assert_eq!(datetime.timestamp(), -2);
+    assert_eq!(Time::try_from(datetime)?, time);
+
+    let delta = TimeDelta::from(Duration(-1_500_000_000));
+    assert_eq!(delta, TimeDelta::milliseconds(-1_500));
+    assert_eq!(Duration::try_from(delta)?, Duration(-1_500_000_000));
+
+    Ok(())
+}
+
+#[test]
+fn test_bag_times() -> Result<()> {
+    let dir = tempdir()?;
+    let start = Time::try_from(SystemTime::now())?;
+
+    let mut writer = Writer::new(dir.path().join("empty"));
+    writer.open()?;
+    writer.add_connection("/a", "std_msgs/msg/Int64", "cdr", "")?;
+    writer.close()?;
+    let reader = Reader::new(dir.path().join("empty"))?;
+    assert_eq!(reader.start_time(), None);
+    assert_eq!(reader.end_time(), None);
+    assert_eq!(reader.duration(), None);
+
+    let mut writer = Writer::new(dir.path().join("bag"));
+    writer.open()?;
+    let a = writer.add_connection("/a", "std_msgs/msg/Int64", "cdr", "")?;
+    writer.write(&a, start, &[0])?;
+    writer.write(&a, start + Duration(1_000), &[1])?;
+    writer.close()?;
+    let reader = Reader::new(dir.path().join("bag"))?;
+    assert_eq!(reader.start_time(), Some(start));
+    assert_eq!(reader.end_time(), Some(start + Duration(1_001)));
+    assert_eq!(reader.duration(), Some(Duration(1_001)));
+
+    // times in a bogus metadata.yaml that overflow are not reported
+    let mut info: BagFileInfo = serde_yaml::from_str(&fs::read_to_string(&writer.metapath)?)?;
+    info.rosbag2_bagfile_information.duration.nanoseconds = i64::MAX;
+    fs::write(&writer.metapath, serde_yaml::to_string(&info)?)?;
+    let reader = Reader::new(dir.path().join("bag"))?;
+    assert_eq!(reader.start_time(), Some(start));
+    assert_eq!(reader.duration(), None);
+    assert_eq!(reader.end_time(), None);
+    assert_eq!(reader.messages(&Default::default())?.count(), 2);
+
+    Ok(())
+}
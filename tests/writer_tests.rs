@@ -1,5 +1,5 @@
 use anyhow::{Ok, Result};
-use rosbag2_rs::{BagFileInfo, Writer};
+use rosbag2_rs::{BagFileInfo, Rosbag2Error, Writer};
 use rusqlite::Connection;
 use std::fs::{self, File};
 use tempfile::tempdir;
@@ -47,8 +47,8 @@ fn test_open_existing_db() -> Result<()> {
     let mut writer = Writer::new(dir.path());
     let result = writer.open();
     assert!(
-        result.is_err(),
-        "Expected error when opening existing database, but got Ok"
+        matches!(result, Err(Rosbag2Error::AlreadyExists(_))),
+        "Expected error when opening existing database, but got {result:?}"
     );
     Ok(())
 }
@@ -95,3 +95,49 @@ fn test_write_operations() -> Result<()> {
     assert_eq!(metadata.topics_with_message_count[0].message_count, 10);
     Ok(())
 }
+
+#[test]
+fn test_write_errors() -> Result<()> {
+    let dir = tempdir()?;
+
+    let mut writer = Writer::new(dir.path());
+    assert!(matches!(
+        writer.add_connection("topic1", "msgtype1", "cdr", ""),
+        Err(Rosbag2Error::NotOpen)
+    ));
+
+    writer.open()?;
+    let connection = writer.add_connection("topic1", "msgtype1", "cdr", "")?;
+    assert!(matches!(
+        writer.add_connection("topic1", "msgtype1", "cdr", ""),
+        Err(Rosbag2Error::DuplicateConnection { .. })
+    ));
+
+    let mut unknown = connection.clone();
+    unknown.id = 42;
+    assert!(matches!(
+        writer.write(&unknown, 0, &[]),
+        Err(Rosbag2Error::UnknownConnection { id: 42, .. })
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_close_empty_bag() -> Result<()> {
+    let dir = tempdir()?;
+    let bag_path = dir.path().join("empty");
+
+    let mut writer = Writer::new(&bag_path);
+    writer.open()?;
+    writer.add_connection("topic1", "msgtype1", "cdr", "")?;
+    writer.close()?;
+
+    // the file paths are relative to the bag directory, like rosbag2 writes them
+    let bag_info: BagFileInfo = serde_yaml::from_str(&fs::read_to_string(&writer.metapath)?)?;
+    let metadata = bag_info.rosbag2_bagfile_information;
+    assert_eq!(metadata.message_count, 0);
+    assert_eq!(metadata.relative_file_paths, vec!["empty.db3"]);
+    assert_eq!(metadata.files[0].path, "empty.db3");
+    assert_eq!(metadata.files[0].message_count, 0);
+    Ok(())
+}